@@ -20,11 +20,17 @@ IconImage {
 
     layer.onEnabledChanged: {
         if (layer.enabled && status === Image.Ready)
-        CUtils.getDominantColor(this, c => dominantColor = c);
+        CUtils.getDominantColor(this, c => {
+            if (c)
+            dominantColor = c;
+        });
     }
 
     onStatusChanged: {
         if (layer.enabled && status === Image.Ready)
-        CUtils.getDominantColor(this, c => dominantColor = c);
+        CUtils.getDominantColor(this, c => {
+            if (c)
+            dominantColor = c;
+        });
     }
 }
@@ -0,0 +1,32 @@
+import qs.utils
+import Vela
+import Quickshell
+import QtQuick
+
+Image {
+    id: root
+
+    property alias path: manager.path
+
+    asynchronous: true
+    fillMode: Image.PreserveAspectCrop
+    source: manager.cachePath
+
+    onWidthChanged: manager.updateSource()
+    onHeightChanged: manager.updateSource()
+
+    Connections {
+        target: root.QsWindow.window
+
+        function onDevicePixelRatioChanged(): void {
+            manager.updateSource();
+        }
+    }
+
+    CachingImageManager {
+        id: manager
+
+        item: root
+        cacheDir: Qt.resolvedUrl(Paths.imagecache)
+    }
+}
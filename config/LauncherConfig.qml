@@ -12,6 +12,7 @@ JsonObject {
     property bool vimKeybinds: false
     property UseFuzzy useFuzzy: UseFuzzy {}
     property Sizes sizes: Sizes {}
+    property Calc calc: Calc {}
 
     component UseFuzzy: JsonObject {
         property bool apps: false
@@ -21,6 +22,15 @@ JsonObject {
         property bool wallpapers: false
     }
 
+    component Calc: JsonObject {
+        property int precision: 10 // Significant digits for inexact results
+        property string outputFormat: "decimal" // One of "decimal", "fraction" or "scientific"
+        property bool programmer: false // Wrap integers to machine words and show hex, octal and binary
+        property int wordSize: 64 // Bits per word in programmer mode, 0 for unbounded
+        property bool signed: true // Two's complement words in programmer mode
+        property string ratesUrl: "https://www.ecb.europa.eu/stats/eurofxref/eurofxref-daily.xml" // Exchange rates (ECB XML or JSON), refreshed daily; empty stays offline
+    }
+
     component Sizes: JsonObject {
         property int itemWidth: 600
         property int itemHeight: 57
@@ -76,7 +76,12 @@ MouseArea {
 
     function save(): void {
         const tmpfile = `file:///tmp/vela-picker-${Quickshell.processId}-${Date.now()}.png`;
-        VUtils.saveItem(screencopy, tmpfile, Qt.react(Math.ceil(rsx), Math.ceil(rsy), Math.floor(sw), Math.floor(sh)), path => Quickshell.execDetached(["swappy", "-f", path]));
+        CUtils.saveItem(screencopy, tmpfile, Qt.rect(Math.ceil(rsx), Math.ceil(rsy), Math.floor(sw), Math.floor(sh)), (path, error) => {
+            if (error)
+                console.warn("Picker: unable to save screenshot:", error);
+            else
+                Quickshell.execDetached(["swappy", "-f", path]);
+        });
         closeAnim.start();
     }
 
@@ -22,10 +22,15 @@ Item {
             filterLabel: qsTr("Image files")
             filters: Images.validImageExtensions
             onAccepted: path => {
-                if (CUtils.copyFile(Qt.resolvedUrl(path), Qt.resolvedUrl(`${Paths.home}/.face`)))
+                const result = CUtils.copyFile(Qt.resolvedUrl(path), Qt.resolvedUrl(`${Paths.home}/.face`));
+                if (result.ok)
                 Quickshell.execDetached(["notify-send", "-a", "vela-shell", "-u", "low", "-h", `STRING:image-path:${path}`, "Profile picture changed", `Profile picture changed to ${Paths.shortenHome(path)}`]);
+                else if (result.error === "permission")
+                Quickshell.execDetached(["notify-send", "-a", "vela-shell", "-u", "critical", "Unable to change profile picture", `Permission denied: ${result.message}`]);
+                else if (result.error === "notFound")
+                Quickshell.execDetached(["notify-send", "-a", "vela-shell", "-u", "critical", "Unable to change profile picture", `${Paths.shortenHome(path)} no longer exists`]);
                 else
-                Quickshell.execDetached(["notify-send", "-a", "vela-shell", "-u", "critical", "Unable to change profile picture", `Failed to change profile picture to ${Paths.shortenHome(path)}`]);
+                Quickshell.execDetached(["notify-send", "-a", "vela-shell", "-u", "critical", "Unable to change profile picture", `Failed to change profile picture to ${Paths.shortenHome(path)}: ${result.message}`]);
             }
         }
     }
@@ -1,6 +1,7 @@
 import qs.components
 import qs.services
 import qs.config
+import qs.utils
 import Vela
 import Quickshell
 import QtQuick
@@ -11,14 +12,197 @@ Item {
 
     required property var list
     readonly property string math: list.search.text.slice(`${Config.launcher.actionPrefix}calc `.length)
+    // With no expression typed, previous calculations can be scrolled through and copied again
+    property int historyIndex: 0
+    readonly property var recalled: Qalculator.history[Math.min(historyIndex, Qalculator.history.length - 1)] ?? null
+
+    // `simplify …`, `solve … [for x]`, `diff … [for x]` and `integrate … [for x]` are done symbolically,
+    // as are equations like `2x + 3 = 11` that are not variable assignments
+    readonly property var symbolic: {
+        const command = math.match(/^\s*(simplify|solve|diff|integrate)\s+(.+?)(?:\s+for\s+([A-Za-z_]\w*))?\s*$/);
+        if (command)
+            return {
+                operation: command[1],
+                expression: command[2],
+                variable: command[3] ?? ""
+            };
+        if (/[^=!<>:]=(?!=)/.test(math) && !/^\s*[A-Za-z_]\w*\s*:?=/.test(math))
+            return {
+                operation: "solve",
+                expression: math,
+                variable: ""
+            };
+        return null;
+    }
 
     function onClicked(): void {
-        Quickshell.execDetached(["wl-copy", Qalculator.eval(math, false)]);
+        if (symbolic) {
+            if (!symbolicResult?.valid)
+                return;
+            Quickshell.execDetached(["wl-copy", symbolicResult.plain]);
+        } else if (math) {
+            // Commit what is shown rather than evaluating again, which could differ for rand() or currencies
+            if (!evaluation?.valid || evaluatedMath !== math)
+                return;
+            Qalculator.commit(math, evaluation.value, (result, error) => {
+                if (result)
+                    Quickshell.execDetached(["wl-copy", result]);
+                else
+                    console.warn("Calculator:", error);
+            });
+        } else if (recalled)
+            Quickshell.execDetached(["wl-copy", recalled.result]);
+        else
+            return;
         root.list.visibilities.launcher = false;
     }
 
+    // Evaluated off the UI thread, see evaluate()
+    property var bases: ({})
+    property var evaluation: null
+    property var symbolicResult: null
+    // The text `evaluation` belongs to, which lags `math` while typing
+    property string evaluatedMath
+    // Set when the result converts currencies, so stale rates are visible
+    readonly property real ratesTimestamp: evaluation?.valid ? evaluation.ratesTimestamp : 0
+    // Shown while the expression is being typed and does not evaluate yet
+    property string lastValue
+
+    function escape(text: string): string {
+        return text.replace(/&/g, "&amp;").replace(/</g, "&lt;").replace(/>/g, "&gt;");
+    }
+
+    function highlighted(): string {
+        const text = evaluatedMath;
+        const start = evaluation.errorStart;
+        if (start < 0)
+            return escape(text);
+        const end = start + evaluation.errorLength;
+        return `${escape(text.slice(0, start))}<u><font color="${Colors.palette.m3error}">${escape(text.slice(start, end)) || "&nbsp;"}</font></u>${escape(text.slice(end))}`;
+    }
+
+    function evaluate(): void {
+        const expr = math;
+        symbolicResult = null;
+        if (!expr) {
+            evaluation = null;
+            bases = {};
+            return;
+        }
+        if (symbolic) {
+            const {
+                operation,
+                expression,
+                variable
+            } = symbolic;
+            const done = result => {
+                if (root.math === expr)
+                    root.symbolicResult = result;
+            };
+            evaluation = null;
+            bases = {};
+            if (operation === "simplify")
+                Qalculator.simplify(expression, done);
+            else if (operation === "solve")
+                Qalculator.solve(expression, variable, done);
+            else if (operation === "diff")
+                Qalculator.differentiate(expression, variable, done);
+            else
+                Qalculator.integrate(expression, variable, done);
+            return;
+        }
+        Qalculator.evaluate(expr, result => {
+            if (root.math !== expr)
+                return;
+            root.evaluatedMath = expr;
+            root.evaluation = result;
+        });
+        if (Config.launcher.calc.programmer)
+            Qalculator.evalBases(expr, result => {
+                if (root.math === expr)
+                    root.bases = result;
+            });
+        else
+            bases = {};
+    }
+
+    onMathChanged: {
+        historyIndex = 0;
+        if (!math)
+            lastValue = "";
+        evaluate();
+    }
+    Component.onCompleted: evaluate()
+    onEvaluationChanged: {
+        if (evaluation?.valid)
+            lastValue = evaluation.value;
+    }
+
     implicitHeight: Config.launcher.sizes.itemHeight
 
+    Connections {
+        target: Config.launcher.calc
+
+        function onProgrammerChanged(): void {
+            root.evaluate();
+        }
+    }
+
+    Binding {
+        target: Qalculator
+        property: "historyPath"
+        value: `${Paths.state}/calc_history`
+    }
+
+    Binding {
+        target: Qalculator
+        property: "ratesPath"
+        value: `${Paths.cache}/exchange_rates`
+    }
+
+    Binding {
+        target: Qalculator
+        property: "ratesUrl"
+        value: Config.launcher.calc.ratesUrl
+    }
+
+    Binding {
+        target: Qalculator
+        property: "precision"
+        value: Config.launcher.calc.precision
+    }
+
+    Binding {
+        target: Qalculator
+        property: "programmerMode"
+        value: Config.launcher.calc.programmer
+    }
+
+    Binding {
+        target: Qalculator
+        property: "wordSize"
+        value: Config.launcher.calc.wordSize
+    }
+
+    Binding {
+        target: Qalculator
+        property: "signedWords"
+        value: Config.launcher.calc.signed
+    }
+
+    Binding {
+        target: Qalculator
+        property: "outputFormat"
+        value: {
+            const format = Config.launcher.calc.outputFormat;
+            if (format === "fraction")
+                return Qalculator.Fraction;
+            if (format === "scientific")
+                return Qalculator.Scientific;
+            return Qalculator.Decimal;
+        }
+    }
+
     anchors.left: parent?.left
     anchors.right: parent?.right
 
@@ -30,6 +214,16 @@ Item {
         }
     }
 
+    WheelHandler {
+        enabled: !root.math && Qalculator.history.length > 1
+        onWheel: event => {
+            if (event.angleDelta.y < 0)
+                root.historyIndex = Math.min(root.historyIndex + 1, Qalculator.history.length - 1);
+            else if (event.angleDelta.y > 0)
+                root.historyIndex = Math.max(root.historyIndex - 1, 0);
+        }
+    }
+
     RowLayout {
         anchors.left: parent.left
         anchors.right: parent.right
@@ -44,22 +238,64 @@ Item {
             Layout.alignment: Qt.AlignVCenter
         }
 
-        StyledText {
-            id: result
+        ColumnLayout {
+            spacing: 0
+
+            Layout.fillWidth: true
+            Layout.alignment: Qt.AlignVCenter
 
-            color: {
-                if (text.includes("error: ") || text.includes("warning: "))
-                    return Colors.palette.m3error;
-                if (!root.math)
-                    return Colors.palette.m3onSurfaceVariant;
-                return Colors.palette.m3onSurface;
+            StyledText {
+                id: result
+
+                color: (root.symbolic ? root.symbolicResult?.valid : root.evaluation?.valid) ? Colors.palette.m3onSurface : Colors.palette.m3onSurfaceVariant
+                textFormat: Text.StyledText
+
+                text: {
+                    if (root.symbolic) {
+                        const symbolic = root.symbolicResult;
+                        if (!symbolic?.valid)
+                            return root.escape(symbolic?.error ? `${root.math} (${symbolic.error})` : root.math);
+                        // Solutions already read `x = 4`; other operations show what was transformed
+                        if (root.symbolic.operation === "solve")
+                            return root.escape(symbolic.pretty);
+                        return root.escape(`${symbolic.expression} = ${symbolic.pretty}`);
+                    }
+                    const evaluation = root.evaluation;
+                    if (evaluation?.valid)
+                        return root.escape(`${evaluation.expression} = ${evaluation.value}`);
+                    if (evaluation) {
+                        const value = evaluation.value || root.lastValue;
+                        return value ? `${root.highlighted()} = ${root.escape(value)}` : root.highlighted();
+                    }
+                    if (root.recalled)
+                        return root.escape(`${root.recalled.expression} = ${root.recalled.result}`);
+                    return qsTr("Type an expression to calculate");
+                }
+                elide: Text.ElideLeft
+
+                Layout.fillWidth: true
             }
 
-            text: root.math.length > 0 ? Qalculator.eval(root.math) : qsTr("Type an expression to calculate")
-            elide: Text.ElideLeft
+            StyledText {
+                visible: text.length > 0
+                text: root.bases.hex ? `${root.bases.hex}  ${root.bases.oct}  ${root.bases.bin}` : ""
+                color: Colors.palette.m3onSurfaceVariant
+                font.pointSize: Appearance.font.size.small
+                font.family: Appearance.font.family.mono
+                elide: Text.ElideLeft
 
-            Layout.fillWidth: true
-            Layout.alignment: Qt.AlignVCenter
+                Layout.fillWidth: true
+            }
+
+            StyledText {
+                visible: root.ratesTimestamp > 0
+                text: visible ? qsTr("Exchange rates from %1").arg(Qt.formatDateTime(new Date(root.ratesTimestamp), "d MMM yyyy")) : ""
+                color: Colors.palette.m3onSurfaceVariant
+                font.pointSize: Appearance.font.size.small
+                elide: Text.ElideRight
+
+                Layout.fillWidth: true
+            }
         }
 
         StyledRect {
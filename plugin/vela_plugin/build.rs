@@ -1,3 +1,12 @@
 fn main() {
     cxx_qt_build::build("src/lib.rs");
+
+    let qalculate = pkg_config::probe_library("libqalculate").expect("libqalculate is required");
+    cxx_build::bridge("src/qalc.rs")
+        .file("cpp/qalc.cpp")
+        .includes(&qalculate.include_paths)
+        .std("c++20")
+        .compile("vela_qalc");
+    println!("cargo:rerun-if-changed=cpp/qalc.h");
+    println!("cargo:rerun-if-changed=cpp/qalc.cpp");
 }
@@ -0,0 +1,431 @@
+#include "vela_plugin/cpp/qalc.h"
+#include "vela_plugin/src/qalc.rs.h"
+
+#include <libqalculate/qalculate.h>
+
+#include <cctype>
+#include <cstdio>
+#include <optional>
+#include <string>
+
+namespace Vela::qalc {
+
+namespace {
+
+constexpr int DEFAULT_PRECISION = 10;
+
+// Time of the snapshot passed to setExchangeRates, or 0 while libqalculate's
+// own cache is in use.
+int64_t ratesTimestamp = 0;
+
+void ensureCalculator() {
+    if (CALCULATOR) {
+        return;
+    }
+
+    new Calculator();
+    CALCULATOR->loadExchangeRates();
+    CALCULATOR->loadGlobalDefinitions();
+    CALCULATOR->loadLocalDefinitions();
+    // 20 °C to °F converts absolute temperatures, 5 °C + 3 °C adds deltas.
+    CALCULATOR->setTemperatureCalculationMode(TEMPERATURE_CALCULATION_HYBRID);
+}
+
+void drainMessages(Evaluation& out) {
+    while (const CalculatorMessage* message = CALCULATOR->message()) {
+        if (!message->message().empty()) {
+            Severity severity = Severity::Info;
+            if (message->type() == MESSAGE_ERROR) {
+                severity = Severity::Error;
+            } else if (message->type() == MESSAGE_WARNING) {
+                severity = Severity::Warning;
+            }
+            out.messages.push_back(Message{ severity, rust::String(message->message()) });
+        }
+        CALCULATOR->nextMessage();
+    }
+}
+
+void pushError(Evaluation& out, const std::string& text) {
+    out.messages.push_back(Message{ Severity::Error, rust::String(text) });
+}
+
+// Radix named by a `to` suffix, or 0 if it names something else.
+int parseBase(std::string to) {
+    remove_blank_ends(to);
+    for (char& c : to) {
+        c = static_cast<char>(std::tolower(static_cast<unsigned char>(c)));
+    }
+    if (to == "hex" || to == "hexadecimal") {
+        return BASE_HEXADECIMAL;
+    }
+    if (to == "oct" || to == "octal") {
+        return BASE_OCTAL;
+    }
+    if (to == "bin" || to == "binary") {
+        return BASE_BINARY;
+    }
+    if (to == "dec" || to == "decimal") {
+        return BASE_DECIMAL;
+    }
+    return 0;
+}
+
+// Reduce `n` to a `bits` wide machine word, as two's complement if signed.
+Number wrapToWord(Number n, int bits, bool isSigned) {
+    if (bits <= 0) {
+        return n;
+    }
+    Number modulus(2, 1);
+    modulus.raise(Number(bits, 1));
+    n.mod(modulus);
+    if (n.isNegative()) {
+        n.add(modulus);
+    }
+    if (isSigned) {
+        Number half(modulus);
+        half.divide(Number(2, 1));
+        if (n.isGreaterThanOrEqualTo(half)) {
+            n.subtract(modulus);
+        }
+    }
+    return n;
+}
+
+std::string printInBase(const MathStructure& value, int base, const Options& options) {
+    PrintOptions po;
+    po.base = base;
+    po.base_display = BASE_DISPLAY_NORMAL;
+    po.min_exp = EXP_NONE;
+    po.twos_complement = options.is_signed;
+    po.hexadecimal_twos_complement = options.is_signed;
+    po.binary_bits = options.word_size > 0 ? options.word_size : 0;
+    MathStructure formatted(value);
+    formatted.format(po);
+    return formatted.print(po);
+}
+
+// Integer evaluation for programmer mode: the result wraps to the configured
+// word size and is printed in every base. Returns nothing for non-integer
+// results and non-base `to` conversions, which take the general path.
+std::optional<Evaluation> evaluateProgrammer(const std::string& input, const Options& options,
+    const EvaluationOptions& eo, int32_t timeout_ms) {
+    std::string expr = input;
+    std::string to;
+    CALCULATOR->separateToExpression(expr, to, eo, true);
+    int base = BASE_DECIMAL;
+    if (!to.empty()) {
+        base = parseBase(to);
+        if (base == 0) {
+            return std::nullopt;
+        }
+    }
+
+    MathStructure value;
+    MathStructure parsedStruct;
+    if (!CALCULATOR->calculate(&value, expr, timeout_ms, eo, &parsedStruct)
+        || !value.isNumber() || !value.number().isInteger()) {
+        // The general path evaluates again and reports its own messages.
+        CALCULATOR->clearMessages();
+        return std::nullopt;
+    }
+    value.set(wrapToWord(value.number(), options.word_size, options.is_signed));
+
+    Evaluation out;
+    drainMessages(out);
+    PrintOptions parsedPo;
+    parsedPo.use_unicode_signs = true;
+    out.parsed = rust::String(parsedStruct.print(parsedPo) + (to.empty() ? "" : " to " + to));
+    out.result = rust::String(printInBase(value, base, options));
+    for (int b : { BASE_DECIMAL, BASE_HEXADECIMAL, BASE_OCTAL, BASE_BINARY }) {
+        out.bases.push_back(rust::String(printInBase(value, b, options)));
+    }
+    return out;
+}
+
+bool isCurrency(const Unit* unit) {
+    const Unit* euro = CALCULATOR->getUnitById(UNIT_ID_EURO);
+    return unit && (unit == euro || unit->baseUnit() == euro);
+}
+
+bool containsCurrency(const MathStructure& m) {
+    if (m.isUnit() && isCurrency(m.unit())) {
+        return true;
+    }
+    for (size_t i = 0; i < m.size(); ++i) {
+        if (containsCurrency(m[i])) {
+            return true;
+        }
+    }
+    return false;
+}
+
+// Snapshot time of the rates `input` depends on, or 0 if it names no
+// currency. Parsing again is cheap next to calculating.
+int64_t ratesTime(const std::string& input, const EvaluationOptions& eo) {
+    std::string expr = input;
+    std::string to;
+    CALCULATOR->separateToExpression(expr, to, eo, true);
+    const bool uses = containsCurrency(CALCULATOR->parse(expr, eo.parse_options))
+        || (!to.empty() && containsCurrency(CALCULATOR->parse(to, eo.parse_options)));
+    if (!uses) {
+        return 0;
+    }
+    if (ratesTimestamp > 0) {
+        return ratesTimestamp;
+    }
+    return static_cast<int64_t>(CALCULATOR->getExchangeRatesTime());
+}
+
+// First free symbol or unknown variable in `m`, depth first.
+const MathStructure* findUnknown(const MathStructure& m) {
+    if (m.isSymbolic() || (m.isVariable() && !m.variable()->isKnown())) {
+        return &m;
+    }
+    for (size_t i = 0; i < m.size(); ++i) {
+        if (const MathStructure* unknown = findUnknown(m[i])) {
+            return unknown;
+        }
+    }
+    return nullptr;
+}
+
+std::string printSymbolic(const MathStructure& value, bool unicode) {
+    PrintOptions po;
+    po.use_unicode_signs = unicode;
+    po.number_fraction_format = FRACTION_FRACTIONAL;
+    po.interval_display = INTERVAL_DISPLAY_SIGNIFICANT_DIGITS;
+    if (!unicode) {
+        po.multiplication_sign = MULTIPLICATION_SIGN_ASTERISK;
+        po.division_sign = DIVISION_SIGN_SLASH;
+    }
+    MathStructure formatted(value);
+    formatted.format(po);
+    return formatted.print(po);
+}
+
+// `x = a` for a single solution, `x = a or x = b` for several.
+std::string printSolutions(const MathStructure& value, const MathStructure& variable,
+    bool unicode) {
+    const std::string name = printSymbolic(variable, unicode);
+    if (!value.isVector()) {
+        return name + " = " + printSymbolic(value, unicode);
+    }
+    std::string out;
+    for (size_t i = 0; i < value.size(); ++i) {
+        out += (i ? " or " : "") + name + " = " + printSymbolic(value[i], unicode);
+    }
+    return out;
+}
+
+} // namespace
+
+Evaluation evaluate(rust::Str expr, const Options& options, int32_t timeout_ms) {
+    ensureCalculator();
+    // libqalculate works on GMP integers and rationals, so exact results stay
+    // exact at any size; precision only applies once something is irrational.
+    CALCULATOR->setPrecision(options.precision > 0 ? options.precision : DEFAULT_PRECISION);
+
+    EvaluationOptions eo;
+    eo.approximation = APPROXIMATION_TRY_EXACT;
+    const std::string input = CALCULATOR->unlocalizeExpression(std::string(expr), eo.parse_options);
+
+    if (options.programmer) {
+        if (std::optional<Evaluation> out = evaluateProgrammer(input, options, eo, timeout_ms)) {
+            return std::move(*out);
+        }
+    }
+
+    PrintOptions po;
+    po.use_unicode_signs = true;
+    po.interval_display = INTERVAL_DISPLAY_SIGNIFICANT_DIGITS;
+    if (options.format == Format::Fraction) {
+        po.number_fraction_format = FRACTION_FRACTIONAL;
+    } else if (options.format == Format::Scientific) {
+        po.number_fraction_format = FRACTION_DECIMAL;
+        po.min_exp = EXP_SCIENTIFIC;
+    } else {
+        po.number_fraction_format = FRACTION_DECIMAL;
+        po.min_exp = EXP_PRECISION;
+    }
+
+    std::string parsed;
+    const std::string result = CALCULATOR->calculateAndPrint(input, timeout_ms, eo, po, &parsed);
+
+    Evaluation out;
+    out.result = rust::String(result);
+    out.parsed = rust::String(parsed);
+    drainMessages(out);
+    out.rates_time = ratesTime(input, eo);
+    // Parsing may raise the same messages again.
+    CALCULATOR->clearMessages();
+    return out;
+}
+
+Evaluation define(rust::Str name, rust::Str expr, int32_t timeout_ms) {
+    ensureCalculator();
+
+    Evaluation out;
+    const std::string varName(name);
+    if (!CALCULATOR->variableNameIsValid(varName)) {
+        pushError(out, "invalid variable name: " + varName);
+        return out;
+    }
+    Variable* existing = CALCULATOR->getActiveVariable(varName);
+    if (existing && (!existing->isLocal() || !existing->isKnown())) {
+        pushError(out, varName + " is a built-in and cannot be reassigned");
+        return out;
+    }
+    // Functions and units share the namespace; shadowing them would change
+    // the meaning of unrelated expressions.
+    if (CALCULATOR->getActiveFunction(varName) || CALCULATOR->getActiveUnit(varName)) {
+        pushError(out, varName + " is already a function or unit");
+        return out;
+    }
+
+    EvaluationOptions eo;
+    eo.approximation = APPROXIMATION_TRY_EXACT;
+    MathStructure value;
+    const std::string input = CALCULATOR->unlocalizeExpression(std::string(expr), eo.parse_options);
+    if (!CALCULATOR->calculate(&value, input, timeout_ms, eo)) {
+        pushError(out, "calculation timed out");
+        return out;
+    }
+    drainMessages(out);
+    for (const Message& message : out.messages) {
+        if (message.severity == Severity::Error) {
+            return out;
+        }
+    }
+
+    if (existing) {
+        static_cast<KnownVariable*>(existing)->set(value);
+    } else {
+        CALCULATOR->addVariable(new KnownVariable("Temporary", varName, value));
+    }
+
+    PrintOptions exact;
+    exact.number_fraction_format = FRACTION_FRACTIONAL;
+    exact.interval_display = INTERVAL_DISPLAY_PLUSMINUS;
+    exact.use_unicode_signs = false;
+    out.result = rust::String(value.print(exact));
+    out.parsed = rust::String(varName);
+    return out;
+}
+
+void undefine(rust::Str name) {
+    ensureCalculator();
+
+    Variable* variable = CALCULATOR->getActiveVariable(std::string(name));
+    if (variable && variable->isLocal() && variable->isKnown()) {
+        variable->destroy();
+    }
+}
+
+Evaluation symbolic(rust::Str expr, Operation operation, rust::Str variable, int32_t timeout_ms) {
+    ensureCalculator();
+
+    EvaluationOptions eo;
+    eo.approximation = APPROXIMATION_EXACT;
+    eo.structuring = STRUCTURING_SIMPLIFY;
+    // Any undefined name is a free variable here, not an error.
+    eo.parse_options.unknowns_enabled = true;
+
+    Evaluation out;
+    const std::string input = CALCULATOR->unlocalizeExpression(std::string(expr), eo.parse_options);
+    MathStructure parsed = CALCULATOR->parse(input, eo.parse_options);
+    drainMessages(out);
+
+    MathStructure var;
+    if (variable.empty()) {
+        const MathStructure* unknown = findUnknown(parsed);
+        var = unknown ? *unknown : CALCULATOR->parse("x", eo.parse_options);
+    } else {
+        var = CALCULATOR->parse(std::string(variable), eo.parse_options);
+        if (!var.isSymbolic() && !var.isVariable()) {
+            pushError(out, "not a variable: " + std::string(variable));
+            return out;
+        }
+    }
+
+    MathFunction* function = nullptr;
+    std::string verb;
+    if (operation == Operation::Solve) {
+        function = CALCULATOR->f_solve;
+        verb = "solve";
+    } else if (operation == Operation::Differentiate) {
+        function = CALCULATOR->f_diff;
+        verb = "differentiate";
+    } else if (operation == Operation::Integrate) {
+        function = CALCULATOR->f_integrate;
+        verb = "integrate";
+    }
+
+    MathStructure value(parsed);
+    if (function) {
+        value.set(function, &parsed, &var, NULL);
+    }
+    if (!CALCULATOR->calculate(&value, timeout_ms, eo)) {
+        pushError(out, "calculation timed out");
+        return out;
+    }
+    drainMessages(out);
+    // What libqalculate cannot do comes back as the unevaluated call.
+    if (function && value.containsFunction(function) > 0) {
+        pushError(out, "unable to " + verb + " for " + printSymbolic(var, true));
+        return out;
+    }
+
+    out.parsed = rust::String(printSymbolic(parsed, true));
+    if (operation == Operation::Solve) {
+        out.result = rust::String(printSolutions(value, var, true));
+        out.plain = rust::String(printSolutions(value, var, false));
+    } else if (operation == Operation::Integrate) {
+        // libqalculate leaves the constant of integration out.
+        out.result = rust::String(printSymbolic(value, true) + " + C");
+        out.plain = rust::String(printSymbolic(value, false) + " + C");
+    } else {
+        out.result = rust::String(printSymbolic(value, true));
+        out.plain = rust::String(printSymbolic(value, false));
+    }
+    return out;
+}
+
+size_t setExchangeRates(rust::Slice<const Rate> rates, int64_t timestamp) {
+    ensureCalculator();
+
+    Unit* euro = CALCULATOR->getUnitById(UNIT_ID_EURO);
+    size_t applied = 0;
+    for (const Rate& rate : rates) {
+        const std::string code(rate.currency);
+        if (rate.per_euro <= 0) {
+            continue;
+        }
+        Unit* unit = CALCULATOR->getActiveUnit(code);
+        if (!unit) {
+            // Never let a new currency shadow a variable or function.
+            if (CALCULATOR->getActiveVariable(code) || CALCULATOR->getActiveFunction(code)) {
+                continue;
+            }
+            unit = CALCULATOR->addUnit(
+                new AliasUnit("Currency", code, "", "", code, euro, "1", 1, "", false, true, true));
+        }
+        if (unit == euro || unit->subtype() != SUBTYPE_ALIAS_UNIT
+            || static_cast<AliasUnit*>(unit)->firstBaseUnit() != euro) {
+            continue;
+        }
+        // One unit of the currency is worth 1/rate euros.
+        char relation[64];
+        std::snprintf(relation, sizeof relation, "1/%.17g", rate.per_euro);
+        AliasUnit* alias = static_cast<AliasUnit*>(unit);
+        alias->setExpression(relation);
+        alias->setApproximate(true);
+        ++applied;
+    }
+    if (applied > 0) {
+        ratesTimestamp = timestamp;
+    }
+    return applied;
+}
+
+} // namespace Vela::qalc
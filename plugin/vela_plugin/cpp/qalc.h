@@ -0,0 +1,37 @@
+#pragma once
+
+#include "rust/cxx.h"
+
+#include <cstdint>
+
+namespace Vela::qalc {
+
+enum class Operation : uint8_t;
+struct Evaluation;
+struct Options;
+struct Rate;
+
+// Evaluate `expr` with libqalculate, giving up after `timeout_ms`. The global
+// calculator is created and loaded on first use; callers must serialise.
+Evaluation evaluate(rust::Str expr, const Options& options, int32_t timeout_ms);
+
+// Calculate `expr` and bind the value to the user variable `name`, replacing
+// an earlier definition. The result is the value printed exactly, suitable
+// for persisting and defining again later.
+Evaluation define(rust::Str name, rust::Str expr, int32_t timeout_ms);
+
+// Remove the user variable `name` defined by `define`, if there is one.
+void undefine(rust::Str name);
+
+// Simplify `expr`, or solve, differentiate or integrate it with respect to
+// `variable` (the first unknown in `expr` if empty). The result is printed
+// with unicode signs; `plain` holds the same in ASCII.
+Evaluation symbolic(rust::Str expr, Operation operation, rust::Str variable, int32_t timeout_ms);
+
+// Replace the exchange rates of the currency units with `rates`, given per
+// euro, and report `timestamp` (seconds since the epoch) for results that use
+// them. ISO codes libqalculate does not know are added as currencies.
+// Returns how many rates were applied.
+size_t setExchangeRates(rust::Slice<const Rate> rates, int64_t timestamp);
+
+} // namespace Vela::qalc
@@ -0,0 +1,158 @@
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread::{self, JoinHandle};
+
+use crate::audio_collector::{AudioCollector, SampleBuffer};
+
+/// What an analyser thread reads from its collector.
+pub(crate) struct Source {
+    pub samples: Arc<SampleBuffer>,
+    pub sample_rate: u32,
+    pub chunk_size: usize,
+    stop: Arc<AtomicBool>,
+}
+
+impl Source {
+    pub fn stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+}
+
+/// Analyser thread over an `AudioCollector`. The collector keeps capturing
+/// while the thread runs; stopping joins the thread and releases it.
+pub(crate) struct Worker {
+    thread: Option<JoinHandle<()>>,
+    stop_flag: Arc<AtomicBool>,
+    /// Collector acquired for the running thread.
+    collector: *mut AudioCollector,
+}
+
+impl Default for Worker {
+    fn default() -> Self {
+        Self {
+            thread: None,
+            stop_flag: Arc::new(AtomicBool::new(false)),
+            collector: std::ptr::null_mut(),
+        }
+    }
+}
+
+impl Worker {
+    /// Acquire `collector` and run `body` on a new thread, unless already
+    /// running or there is no collector.
+    pub fn start(
+        &mut self,
+        collector: *mut AudioCollector,
+        body: impl FnOnce(Source) + Send + 'static,
+    ) {
+        if self.thread.is_some() {
+            return;
+        }
+        let Some(target) = (unsafe { collector.as_mut() }) else {
+            return;
+        };
+        target.acquire();
+        self.collector = collector;
+        self.stop_flag.store(false, Ordering::Relaxed);
+        let source = Source {
+            samples: target.samples(),
+            sample_rate: target.sample_rate(),
+            chunk_size: target.chunk_size() as usize,
+            stop: self.stop_flag.clone(),
+        };
+        self.thread = Some(thread::spawn(move || body(source)));
+    }
+
+    /// Stop the thread and release its collector. Returns whether it was
+    /// running.
+    pub fn stop(&mut self) -> bool {
+        let Some(handle) = self.thread.take() else {
+            return false;
+        };
+        self.stop_flag.store(true, Ordering::Relaxed);
+        let _ = handle.join();
+        if let Some(collector) = unsafe { self.collector.as_mut() } {
+            collector.release();
+        }
+        self.collector = std::ptr::null_mut();
+        true
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// A `Service` that analyses an `AudioCollector` on a `Worker`.
+pub(crate) trait Analyser {
+    fn worker(&mut self) -> &mut Worker;
+    fn collector_mut(&mut self) -> &mut *mut AudioCollector;
+    fn collector_changed(&self);
+    fn start(&mut self);
+
+    /// Body of the `collector` setter; moves a running worker over to the
+    /// new collector.
+    fn switch_collector(&mut self, collector: *mut AudioCollector) {
+        if *self.collector_mut() == collector {
+            return;
+        }
+        let running = self.worker().stop();
+        *self.collector_mut() = collector;
+        self.collector_changed();
+        if running {
+            self.start();
+        }
+    }
+
+    /// Apply a changed analysis setting by restarting a running worker.
+    fn restart(&mut self) {
+        if self.worker().stop() {
+            self.start();
+        }
+    }
+}
+
+/// Hann-windowed FFT over the most recent `size` samples.
+pub(crate) struct SlidingFft {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    /// Latest samples, oldest first.
+    history: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+}
+
+impl SlidingFft {
+    pub fn new(size: usize) -> Self {
+        let window = (0..size)
+            .map(|i| {
+                let x = std::f32::consts::TAU * i as f32 / (size - 1) as f32;
+                0.5 - 0.5 * x.cos()
+            })
+            .collect();
+        Self {
+            fft: FftPlanner::new().plan_fft_forward(size),
+            window,
+            history: vec![0.0; size],
+            spectrum: vec![Complex::default(); size],
+        }
+    }
+
+    /// Shift `samples` into the history and return its spectrum.
+    pub fn push(&mut self, samples: &[f32]) -> &[Complex<f32>] {
+        let size = self.history.len();
+        let n = samples.len().min(size);
+        self.history.copy_within(n.., 0);
+        self.history[size - n..].copy_from_slice(&samples[samples.len() - n..]);
+        for ((out, &s), &w) in self.spectrum.iter_mut().zip(&self.history).zip(&self.window) {
+            *out = Complex::new(s * w, 0.0);
+        }
+        self.fft.process(&mut self.spectrum);
+        &self.spectrum
+    }
+}
@@ -29,6 +29,10 @@ pub struct AppEntry {
 
     #[qproperty]
     pub frequency: u32,
+
+    /// Launch count decayed by `AppDb.halfLife`; what the launcher ranks by.
+    #[qproperty]
+    pub score: f64,
 }
 
 pub fn register() {
@@ -3,8 +3,26 @@ use qt6_core::{QString, QStringList};
 use rsqlite::{params, Connection};
 use uuid::Uuid;
 
+use std::cmp::Ordering;
+
 use crate::app_entry::AppEntry;
 
+/// Days for a launch to count half as much, until `halfLife` is set.
+const DEFAULT_HALF_LIFE: f64 = 14.0;
+
+const SECONDS_PER_DAY: f64 = 24.0 * 60.0 * 60.0;
+
+/// Schema changes in order, kept track of in `PRAGMA user_version`: entry
+/// `n` takes the database from version `n` to `n + 1`.
+const MIGRATIONS: &[&str] = &[
+    // Counts become scores as if every launch happened at migration time,
+    // so the existing ranking carries over and then decays.
+    "ALTER TABLE frequencies ADD COLUMN score REAL NOT NULL DEFAULT 0;
+    ALTER TABLE frequencies ADD COLUMN last_launched INTEGER NOT NULL DEFAULT 0;
+    UPDATE frequencies SET score = frequency,
+        last_launched = CAST(strftime('%s', 'now') AS INTEGER);",
+];
+
 #[derive(QObject)]
 pub struct AppDb {
     #[qproperty]
@@ -16,6 +34,12 @@ pub struct AppDb {
     #[qproperty]
     pub entries: Vec<crate::app_entry::AppEntry>,
 
+    /// Days after which a launch counts half as much towards `score`, so
+    /// apps used heavily long ago give way to what is used now. Zero or less
+    /// turns decay off and ranks by plain launch count.
+    #[qproperty(cpp_name = "halfLife")]
+    pub half_life: f64,
+
     #[qproperty(read, notify = "appsChanged")]
     apps: Vec<*mut AppEntry>,
 
@@ -28,6 +52,7 @@ impl Default for AppDb {
             uuid: QString::from(Uuid::new_v4().to_string()),
             path: QString::default(),
             entries: vec![],
+            half_life: DEFAULT_HALF_LIFE,
             apps: vec![],
             conn: None,
         }
@@ -42,18 +67,13 @@ impl AppDb {
         }
         self.path = new_path.clone();
         self.conn = None;
-        let db_path = if new_path.is_empty() {
-            ":memory:".to_string()
-        } else {
-            new_path.to_string()
-        };
-        let conn = Connection::open(db_path).expect("Failed to open database");
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS frequencies (id TEXT PRIMARY KEY, frequency INTEGER)",
-            [],
-        )
-        .unwrap();
-        self.conn = Some(conn);
+        if !new_path.is_empty() {
+            match open(&new_path.to_string()) {
+                Ok(conn) => self.conn = Some(conn),
+                // Launches are still ranked for this session, in memory.
+                Err(e) => eprintln!("AppDb: unable to open {new_path}: {e}"),
+            }
+        }
         self.update_app_frequencies();
     }
 
@@ -68,48 +88,72 @@ impl AppDb {
 
     pub fn get_apps(&self) -> Vec<&mut AppEntry> {
         let mut apps: Vec<_> = self.apps.clone();
-        apps.sort_by(|a, b| unsafe {
-            let fa = (**a).frequency;
-            let fb = (**b).frequency;
-            if fa != fb {
-                fb.cmp(&fa)
-            } else {
-                (**a).name.cmp(&(**b).name)
-            }
-        });
+        apps.sort_by(|a, b| unsafe { compare_apps(&**a, &**b) });
         apps
     }
 
+    #[qproperty(cpp_name = "halfLife")]
+    pub fn set_half_life(&mut self, days: f64) {
+        if self.half_life == days {
+            return;
+        }
+        self.half_life = days;
+        self.update_apps();
+        self.halfLifeChanged();
+    }
+
+    /// Record a launch: the stored score is decayed to now and one is added.
     #[qinvokable]
     pub fn incrementFrequency(&mut self, id: &QString) {
-        if let Some(conn) = &self.conn {
+        let now = now();
+        let half_life = self.half_life;
+        if let Some(conn) = self.connection() {
+            let (score, last_launched) = stored_score(conn, id);
+            let score = decay(score, now - last_launched, half_life) + 1.0;
             conn.execute(
-                "INSERT INTO frequencies (id, frequency) VALUES (?1, 1)
-                ON CONFLICT(id) DO UPDATE SET frequency = frequency + 1",
-                params![id.to_string()],
+                "INSERT INTO frequencies (id, frequency, score, last_launched)
+                VALUES (?1, 1, ?2, ?3)
+                ON CONFLICT(id) DO UPDATE SET
+                    frequency = frequency + 1, score = ?2, last_launched = ?3",
+                params![id.to_string(), score, now],
             )
             .unwrap();
         }
-        for entry in &mut self.entries {
-            if entry.id == *id {
-                entry.frequency += 1;
-                break;
-            }
-        }
         self.update_apps();
     }
 
+    /// The database at `path`, or an in-memory one until a path is set or
+    /// when it cannot be opened, so scores decay the same way either way.
+    fn connection(&mut self) -> Option<&Connection> {
+        if self.conn.is_none() {
+            self.conn = open(":memory:")
+                .inspect_err(|e| eprintln!("AppDb: unable to open an in-memory database: {e}"))
+                .ok();
+        }
+        self.conn.as_ref()
+    }
+
     fn update_app_frequencies(&mut self) {
+        let now = now();
+        let half_life = self.half_life;
+        if self.connection().is_none() {
+            return;
+        }
         if let Some(conn) = &self.conn {
             for entry in &mut self.entries {
-                let freq: u32 = conn
+                let (freq, score, last_launched): (u32, f64, i64) = conn
                     .query_row(
-                        "SELECT frequency FROM frequencies WHERE id = ?1",
+                        "SELECT frequency, score, last_launched FROM frequencies WHERE id = ?1",
                         params![entry.id.to_string()],
-                        |row| row.get(0),
+                        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
                     )
-                    .unwrap_or(0);
+                    .unwrap_or((0, 0.0, now));
                 entry.frequency = freq;
+                entry.score = if half_life > 0.0 {
+                    decay(score, now - last_launched, half_life)
+                } else {
+                    freq as f64
+                };
             }
         }
     }
@@ -121,15 +165,7 @@ impl AppDb {
             .iter_mut()
             .map(|e| e as *mut AppEntry)
             .collect();
-        new_apps.sort_by(|a, b| unsafe {
-            let fa = (**a).frequency;
-            let fb = (**b).frequency;
-            if fa != fb {
-                fb.cmp(&fa)
-            } else {
-                (**a).name.cmp(&(**b).name)
-            }
-        });
+        new_apps.sort_by(|a, b| unsafe { compare_apps(&**a, &**b) });
         if new_apps != self.apps {
             self.apps = new_apps;
             self.appsChanged();
@@ -140,6 +176,142 @@ impl AppDb {
     fn appsChanged(&self);
 }
 
+fn open(path: &str) -> Result<Connection, rsqlite::Error> {
+    let conn = Connection::open(path)?;
+    migrate(&conn)?;
+    Ok(conn)
+}
+
+/// Apply the `MIGRATIONS` the database has not seen yet, each in its own
+/// transaction. A failed step is rolled back, leaving the database at the
+/// last version that applied cleanly.
+fn migrate(conn: &Connection) -> Result<(), rsqlite::Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS frequencies (id TEXT PRIMARY KEY, frequency INTEGER)",
+        [],
+    )?;
+    let version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (from, step) in MIGRATIONS.iter().enumerate().skip(version.max(0) as usize) {
+        conn.execute_batch("BEGIN")?;
+        let result = conn
+            .execute_batch(&format!("{step}\nPRAGMA user_version = {};", from + 1))
+            .and_then(|()| conn.execute_batch("COMMIT"));
+        if let Err(e) = result {
+            let _ = conn.execute_batch("ROLLBACK");
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+fn stored_score(conn: &Connection, id: &QString) -> (f64, i64) {
+    conn.query_row(
+        "SELECT score, last_launched FROM frequencies WHERE id = ?1",
+        params![id.to_string()],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+    .unwrap_or((0.0, 0))
+}
+
+/// `score` after `elapsed` seconds of exponential decay. A non-positive
+/// half-life leaves it unchanged.
+fn decay(score: f64, elapsed: i64, half_life_days: f64) -> f64 {
+    if half_life_days <= 0.0 || elapsed <= 0 {
+        return score;
+    }
+    score * 0.5f64.powf(elapsed as f64 / SECONDS_PER_DAY / half_life_days)
+}
+
+/// Highest score first, then by name.
+fn compare_apps(a: &AppEntry, b: &AppEntry) -> Ordering {
+    b.score.total_cmp(&a.score).then_with(|| a.name.cmp(&b.name))
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
 pub fn register() {
     qml_register_type::<AppDb>("Vela", 1, 0, "AppDb");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = SECONDS_PER_DAY as i64;
+
+    fn app(name: &str, score: f64) -> AppEntry {
+        AppEntry {
+            name: QString::from(name),
+            score,
+            ..Default::default()
+        }
+    }
+
+    fn version(conn: &Connection) -> i32 {
+        conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn decay_halves_per_half_life() {
+        assert_eq!(decay(8.0, 0, 14.0), 8.0);
+        assert!((decay(8.0, 14 * DAY, 14.0) - 4.0).abs() < 1e-9);
+        assert!((decay(8.0, 42 * DAY, 14.0) - 1.0).abs() < 1e-9);
+        // Off, or a clock that went backwards, leaves the score alone.
+        assert_eq!(decay(8.0, 14 * DAY, 0.0), 8.0);
+        assert_eq!(decay(8.0, 14 * DAY, -1.0), 8.0);
+        assert_eq!(decay(8.0, -DAY, 14.0), 8.0);
+    }
+
+    #[test]
+    fn compare_apps_ranks_by_score_then_name() {
+        let mut apps = [app("b", 1.0), app("c", 3.0), app("a", 1.0), app("d", 0.0)];
+        apps.sort_by(compare_apps);
+        let names: Vec<String> = apps.iter().map(|a| a.name.to_string()).collect();
+        assert_eq!(names, ["c", "a", "b", "d"]);
+    }
+
+    #[test]
+    fn migrates_counts_to_scores() {
+        let conn = Connection::open(":memory:").unwrap();
+        conn.execute_batch(
+            "CREATE TABLE frequencies (id TEXT PRIMARY KEY, frequency INTEGER);
+            INSERT INTO frequencies VALUES ('firefox', 7), ('foot', 2);",
+        )
+        .unwrap();
+        migrate(&conn).unwrap();
+        assert_eq!(version(&conn), MIGRATIONS.len() as i32);
+        let (score, last_launched): (f64, i64) = conn
+            .query_row(
+                "SELECT score, last_launched FROM frequencies WHERE id = 'firefox'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(score, 7.0);
+        assert!((now() - last_launched).abs() < 60);
+        // Already current, so nothing runs again.
+        migrate(&conn).unwrap();
+    }
+
+    #[test]
+    fn failed_migration_rolls_back() {
+        let conn = Connection::open(":memory:").unwrap();
+        // A version 0 table that somehow has `score` already.
+        conn.execute_batch(
+            "CREATE TABLE frequencies (id TEXT PRIMARY KEY, frequency INTEGER, score REAL);
+            INSERT INTO frequencies VALUES ('firefox', 7, 1.5);",
+        )
+        .unwrap();
+        assert!(migrate(&conn).is_err());
+        assert_eq!(version(&conn), 0);
+        let score: f64 = conn
+            .query_row("SELECT score FROM frequencies", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(score, 1.5);
+        // No transaction was left open.
+        conn.execute_batch("BEGIN; COMMIT;").unwrap();
+    }
+}
@@ -2,12 +2,16 @@ use cxx_qt::QObject;
 use qt6_core::{QColor, QUrl};
 use pipewire as pw;
 
+use std::collections::VecDeque;
 use std::sync::{
-    atomic::{AtomicBool, AtomicPtr, Ordering},
+    atomic::{AtomicBool, Ordering},
     Arc, Mutex,
 };
 use std::thread::{self, JoinHandle};
 
+/// Chunks of recent samples kept for readers that fall behind.
+const RING_CHUNKS: usize = 16;
+
 #[derive(QObject)]
 pub struct AudioCollector {
     /// Sample rate (Hz)
@@ -22,91 +26,82 @@ pub struct AudioCollector {
     #[qproperty(cpp_name = "nodeId")]
     node_id: u32,
 
-    /// Double-buffered storage for captured samples
-    buffer1: Vec<f32>,
-    buffer2: Vec<f32>,
-    read_buffer: AtomicPtr<Vec<f32>>>,
-    write_buffer: AtomicPtr<Vec<f32>>>,
-    
+    /// Captured samples, shared with the capture thread and analysers
+    samples: Arc<SampleBuffer>,
 
     /// Pipewire capture thread and stop flag
     worker: Option<JoinHandle<()>>,
     stop_flag: Arc<AtomicBool>,
+
+    /// Number of providers currently reading from this collector.
+    users: u32,
 }
 
 impl Default for AudioCollector {
     fn default() -> Self {
         let sample_rate = 44_100;
         let chunk_size = 512;
-        let mut buf1 = vec![0.0f32; chunk_size as usize];
-        let mut buf2 = vec![0.0f32; chunk_size as usize];
         Self {
             sample_rate,
             chunk_size,
             node_id: pw::PW_ID_ANY,
-            buffer1: buf1,
-            buffer2: buf2,
-            read_buffer: AtomicPtr::new(&mut buf1 as *mut Vec<f32>),
-            write_buffer: AtomicPtr::new(&mut buf2 as *mut Vec<f32>),
+            samples: Arc::new(SampleBuffer::new(chunk_size as usize * RING_CHUNKS)),
             worker: None,
             stop_flag: Arc::new(AtomicBool::new(false)),
+            users: 0,
         }
     }
 }
 
 
 impl AudioCollector {
-    /// Clears the write buffer and atomically
-    /// swaps it into the read slot.
+    /// Drops all buffered samples; reads return silence until
+    /// new samples arrive.
     #[qinvokable(cpp_name = "clearBuffer")]
     pub fn clear_buffer(&self) {
-        unsafe {
-            let wb = &mut *self.write_buffer.load(Ordering::Relaxed);
-            wb.fill(0.0);
-            let old_read = self.read_buffer.swap(wb, Ordering::AcqRel);
-            self.write_buffer.store(old_read, Ordering::Release);
-        }
+        self.samples.clear();
     }
 
-    /// Copy `count` 16-bit samples into the write buffer,
-    /// normalizing to ±1.0, then swaps read and write pointers.
+    /// Append `count` 16-bit samples, normalizing to ±1.0.
     #[qinvokable(cpp_name = "loadChunk")]
     pub fn load_chunk(&self, samples: &[i16], count: u32) {
-        let count = count.min(self.chunk_size);
-        unsafe {
-            let wb = &mut *self.write_buffer.load(Ordering::Relaxed);
-            for i in 0..(count as usize) {
-                wb[i] = samples[i] as f32 / 32768.0;
-            }
-            let old_read = self.read_buffer.swap(wb, Ordering::AcqRel);
-            self.write_buffer.store(old_read, Ordering::Release);
-        }
+        let count = (count as usize).min(samples.len());
+        self.samples.push_i16(&samples[..count]);
     }
 
-    /// Read up to `count` frames from the current read buffer into `out`.
+    /// Read the latest `count` frames into `out`.
     /// Returns the number of frames copied.
     #[qinvokable(cpp_name = "readChunk")]
     pub fn read_chunk(&self, out: &mut [f32], count: u32) -> u32 {
-        let count = count.min(self.chunk_size).max(1);
-        unsafe {
-            let rb = &*self.read_buffer.load(Ordering::Accquire);
-            out[..count as usize].copy_from_slice(&rb[..count as usize]);
-        }
-        count
+        let count = (count.min(self.chunk_size).max(1) as usize).min(out.len());
+        self.samples.read_latest(&mut out[..count]) as u32
     }
 
-    /// Read up to `count` frames as doubles; QML will convert
+    /// Read the latest `count` frames as doubles; QML will convert
     /// automatically if only the float version is exposed.
     #[qinvokable(cpp_name = "readChunk")]
     pub fn read_chunk_double(&self, out: &mut [f64], count: u32) -> u32 {
-        let count = count.min(self.chunk_size).max(1);
-        unsafe {
-            let rb = &*self.read_buffer.load(Ordering::Accquire);
-            for i in 0..(count as usize) {
-                out[i] = rb[i] as f64;
-            }
+        let count = (count.min(self.chunk_size).max(1) as usize).min(out.len());
+        let mut chunk = vec![0.0f32; count];
+        self.samples.read_latest(&mut chunk);
+        for (o, s) in out.iter_mut().zip(chunk) {
+            *o = s as f64;
         }
-        count
+        count as u32
+    }
+
+    /// Shared sample buffer for analyser threads. It outlives the
+    /// collector if a reader still holds it.
+    pub(crate) fn samples(&self) -> Arc<SampleBuffer> {
+        self.samples.clone()
+    }
+
+    pub(crate) fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub(crate) fn chunk_size(&self) -> u32 {
+        self.chunk_size
     }
 
     /// Setter for nodeId; restarts worker if running.
@@ -155,13 +150,8 @@ impl AudioCollector {
     }
 
     fn realloc_buffers(&mut self) {
-        // Resize the buffers and reset pointers.
-        self.buffer1.resize(self.chunk_size as usize, 0.0);
-        self.buffer2.resize(self.chunk_size as usize, 0.0);
-        self.read_buffer
-            .store(&mut self.buffer1 as *mut Vec<f32>, Ordering::Release);
-        self.write_buffer
-            .store(&mut self.buffer2 as *mut Vec<f32>, Ordering::Release);
+        // Resized in place so readers holding the buffer keep working.
+        self.samples.set_capacity(self.chunk_size as usize * RING_CHUNKS);
     }
 
     // QML signals autogenerated by `cxx_qt` for properties:
@@ -185,9 +175,7 @@ impl AudioCollector {
         let sample_rate = self.sample_rate;
         let chunk_size = self.chunk_size;
         let node_id = self.node_id;
-        // raw pointers to swap buffers in closure
-        let write_ptr = self.write_bugger.load(Ordering::Accquire) as *mut Vec<f32>;
-        let read_ptr = self.read_bugger.load(Ordering::Accquire) as *mut Vec<f32>;
+        let samples = self.samples.clone();
         self.worker = Some(thread::spawn(move || {
             // Init Pipewire
             if pw::init().is_err() {
@@ -242,20 +230,14 @@ impl AudioCollector {
                                 let datas = buf.datas_mut();
                                 if let Some(data) = datas.first() {
                                     if let Some(ptr) = data.data() {
-                                        // Cast pointer to slice of i16 samples
-                                        let sample_count = (data.size() / 2).min(chunk_size as usize);
+                                        // The stream is negotiated as F32LE below.
+                                        let sample_count = (data.size() as usize
+                                            / std::mem::size_of::<f32>())
+                                        .min(chunk_size as usize);
                                         let slice = unsafe {
-                                            std::slice::from_raw_parts(ptr as *const i16, sample_count)
+                                            std::slice::from_raw_parts(ptr as *const f32, sample_count)
                                         };
-                                        // Write into the write buffer
-                                        unsafe {
-                                            let wb = &mut *write_ptr;
-                                            for i in 0..sample_count {
-                                                wb[i] = slice[i] as f32 / 32768.0;
-                                            }
-                                            // Swap read/write
-                                            std::ptr::swap(write_ptrm read_ptr);
-                                        }
+                                        samples.push_f32(slice);
                                     }
                                 }
                                 stream.queue_buffer(buf).unwrap();
@@ -288,6 +270,25 @@ impl AudioCollector {
         }));
     }
 
+    /// Register a reader, starting capture for the first one.
+    pub(crate) fn acquire(&mut self) {
+        self.users += 1;
+        if self.users == 1 {
+            self.start();
+        }
+    }
+
+    /// Unregister a reader, stopping capture once none are left.
+    pub(crate) fn release(&mut self) {
+        if self.users == 0 {
+            return;
+        }
+        self.users -= 1;
+        if self.users == 0 {
+            self.stop();
+        }
+    }
+
     /// Stop the Pipewire capture thread by setting the stop flag
     /// and joining the thread.
     #[cxx_qt::qinvokable]
@@ -299,6 +300,98 @@ impl AudioCollector {
     }
 }
 
+/// Ring of recently captured samples, shared between the capture thread
+/// and the analysers.
+pub(crate) struct SampleBuffer {
+    ring: Mutex<Ring>,
+}
+
+struct Ring {
+    recent: VecDeque<f32>,
+    capacity: usize,
+    /// Samples pushed since creation; readers keep their own count to tell
+    /// new samples from ones they have already seen.
+    written: u64,
+}
+
+impl SampleBuffer {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            ring: Mutex::new(Ring {
+                recent: VecDeque::with_capacity(capacity),
+                capacity: capacity.max(1),
+                written: 0,
+            }),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Ring> {
+        self.ring.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Append 16-bit samples, normalized to ±1.0.
+    pub(crate) fn push_i16(&self, samples: &[i16]) {
+        self.push(samples.iter().map(|&s| s as f32 / 32768.0));
+    }
+
+    /// Append float samples as captured, nominally within ±1.0.
+    pub(crate) fn push_f32(&self, samples: &[f32]) {
+        self.push(samples.iter().copied());
+    }
+
+    fn push(&self, samples: impl ExactSizeIterator<Item = f32>) {
+        let mut ring = self.lock();
+        ring.written += samples.len() as u64;
+        for s in samples {
+            if ring.recent.len() == ring.capacity {
+                ring.recent.pop_front();
+            }
+            ring.recent.push_back(s);
+        }
+    }
+
+    /// Count of samples pushed so far, as a starting `read_new` cursor.
+    pub(crate) fn position(&self) -> u64 {
+        self.lock().written
+    }
+
+    pub(crate) fn clear(&self) {
+        self.lock().recent.clear();
+    }
+
+    fn set_capacity(&self, capacity: usize) {
+        let mut ring = self.lock();
+        ring.capacity = capacity.max(1);
+        let excess = ring.recent.len().saturating_sub(ring.capacity);
+        ring.recent.drain(..excess);
+    }
+
+    /// Append the samples pushed since `cursor` to `out` and advance
+    /// `cursor`. Samples that already left the ring are skipped. Returns
+    /// how many were appended.
+    pub(crate) fn read_new(&self, cursor: &mut u64, out: &mut Vec<f32>) -> usize {
+        let ring = self.lock();
+        let new = (ring.written - (*cursor).min(ring.written)).min(ring.recent.len() as u64);
+        let start = ring.recent.len() - new as usize;
+        out.extend(ring.recent.range(start..));
+        *cursor = ring.written;
+        new as usize
+    }
+
+    /// Fill `out` with the most recent samples, padding with leading
+    /// silence if fewer are buffered. Returns `out.len()`.
+    pub(crate) fn read_latest(&self, out: &mut [f32]) -> usize {
+        let ring = self.lock();
+        let n = out.len().min(ring.recent.len());
+        let pad = out.len() - n;
+        out[..pad].fill(0.0);
+        for (o, s) in out[pad..].iter_mut().zip(ring.recent.range(ring.recent.len() - n..)) {
+            *o = *s;
+        }
+        out.len()
+    }
+}
+
 // Helper: next power of two calculation
 fn next_power_of2(mut n: u32) -> u32 {
     if n == 0 {
@@ -0,0 +1,455 @@
+use cxx_qt::{CxxQtThread, QObject, Threading};
+
+use std::collections::VecDeque;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::analyser::{Analyser, SlidingFft, Worker};
+use crate::audio_collector::AudioCollector;
+
+/// FFT length for the onset detection function.
+const FFT_SIZE: usize = 1024;
+
+/// Seconds of onset envelope used for tempo estimation.
+const ENVELOPE_SECS: f64 = 8.0;
+
+/// Seconds of envelope averaged for the adaptive onset threshold.
+const THRESHOLD_SECS: f64 = 0.5;
+
+/// Onsets must exceed the local mean by this factor.
+const THRESHOLD_RATIO: f64 = 1.4;
+
+/// Tempo search range (BPM) and the prior the estimate is biased towards.
+const MIN_BPM: f64 = 60.0;
+const MAX_BPM: f64 = 200.0;
+const PRIOR_BPM: f64 = 120.0;
+
+/// How often the tempo is re-estimated (seconds).
+const TEMPO_INTERVAL: f64 = 1.0;
+
+/// Onsets within this fraction of a beat period re-align the beat phase.
+const PHASE_TOLERANCE: f64 = 0.2;
+
+/// Below this confidence no beats are emitted.
+const MIN_CONFIDENCE: f64 = 0.15;
+
+#[derive(QObject)]
+#[qobject(base = "Service")]
+pub struct BeatTracker {
+    #[qproperty]
+    collector: *mut AudioCollector,
+
+    /// Current tempo estimate in beats per minute; 0 until one is found.
+    #[qproperty(read, notify = "bpmChanged")]
+    bpm: f64,
+
+    /// How periodic the onset envelope is at `bpm`, from 0 to 1.
+    #[qproperty(read, notify = "confidenceChanged")]
+    confidence: f64,
+
+    worker: Worker,
+}
+
+impl Default for BeatTracker {
+    fn default() -> Self {
+        Self {
+            collector: std::ptr::null_mut(),
+            bpm: 0.0,
+            confidence: 0.0,
+            worker: Worker::default(),
+        }
+    }
+}
+
+impl BeatTracker {
+    #[qproperty(cpp_name = "collector")]
+    pub fn set_collector(&mut self, collector: *mut AudioCollector) {
+        self.switch_collector(collector);
+    }
+
+    fn apply(&mut self, update: Update) {
+        if self.bpm != update.bpm {
+            self.bpm = update.bpm;
+            self.bpmChanged();
+        }
+        if self.confidence != update.confidence {
+            self.confidence = update.confidence;
+            self.confidenceChanged();
+        }
+        if update.beat {
+            self.beat();
+        }
+    }
+
+    #[cxx_qt::qsignal]
+    fn bpmChanged(&self);
+    #[cxx_qt::qsignal]
+    fn confidenceChanged(&self);
+
+    /// Emitted on every tracked beat.
+    #[cxx_qt::qsignal]
+    fn beat(&self);
+
+    /// Called by `Service` when the first reference is taken. Spawns the
+    /// analyser thread, which consumes one collector chunk per period.
+    #[cxx_qt::cxx_override]
+    pub fn start(&mut self) {
+        let qt_thread = self.qt_thread();
+        self.worker.start(self.collector, move |source| {
+            let (samples, sample_rate, hop) =
+                (source.samples.as_ref(), source.sample_rate, source.chunk_size);
+            let period = Duration::from_secs_f64(hop as f64 / sample_rate as f64);
+            let mut detector = BeatDetector::new(sample_rate, hop);
+            let mut cursor = samples.position();
+            let mut pending = Vec::new();
+            let mut last = Update::default();
+            let mut next = Instant::now();
+            while !source.stopped() {
+                samples.read_new(&mut cursor, &mut pending);
+                // Every new sample is consumed exactly once, in whole hops.
+                let mut update: Option<Update> = None;
+                while pending.len() >= hop {
+                    let hop_update = detector.process(&pending[..hop]);
+                    pending.drain(..hop);
+                    let beat = hop_update.beat || update.is_some_and(|u| u.beat);
+                    update = Some(Update { beat, ..hop_update });
+                }
+                if let Some(update) = update {
+                    if update != last && !queue_update(&qt_thread, update) {
+                        return;
+                    }
+                    last = Update { beat: false, ..update };
+                }
+                next += period;
+                match next.checked_duration_since(Instant::now()) {
+                    Some(wait) => thread::sleep(wait),
+                    None => next = Instant::now(),
+                }
+            }
+        });
+    }
+
+    /// Called by `Service` when the last reference is dropped.
+    #[cxx_qt::cxx_override]
+    pub fn stop(&mut self) {
+        self.worker.stop();
+    }
+}
+
+impl Analyser for BeatTracker {
+    fn worker(&mut self) -> &mut Worker {
+        &mut self.worker
+    }
+
+    fn collector_mut(&mut self) -> &mut *mut AudioCollector {
+        &mut self.collector
+    }
+
+    fn collector_changed(&self) {
+        self.collectorChanged();
+    }
+
+    fn start(&mut self) {
+        BeatTracker::start(self);
+    }
+}
+
+fn queue_update(qt_thread: &CxxQtThread<BeatTracker>, update: Update) -> bool {
+    qt_thread
+        .queue(move |tracker: &mut BeatTracker| tracker.apply(update))
+        .is_ok()
+}
+
+/// Result of processing one hop.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub(crate) struct Update {
+    pub bpm: f64,
+    pub confidence: f64,
+    pub beat: bool,
+}
+
+/// Spectral-flux onset detector with an autocorrelation tempo estimator
+/// and a phase-locked beat clock. Feed it consecutive hops of mono samples,
+/// e.g. chunks decoded from a WAV file, to test it offline.
+pub(crate) struct BeatDetector {
+    fft: SlidingFft,
+    prev_magnitudes: Vec<f32>,
+    /// Hops per second.
+    rate: f64,
+    /// Spectral flux per hop, oldest first.
+    envelope: VecDeque<f64>,
+    capacity: usize,
+    /// Hop counter, used as the clock.
+    hop: u64,
+    last_tempo_hop: u64,
+    /// Beat period in hops, once known.
+    period: Option<f64>,
+    next_beat: f64,
+    bpm: f64,
+    confidence: f64,
+}
+
+impl BeatDetector {
+    pub(crate) fn new(sample_rate: u32, hop_size: usize) -> Self {
+        let rate = sample_rate as f64 / hop_size.max(1) as f64;
+        let capacity = (ENVELOPE_SECS * rate).ceil() as usize;
+        Self {
+            fft: SlidingFft::new(FFT_SIZE),
+            prev_magnitudes: vec![0.0; FFT_SIZE / 2],
+            rate,
+            envelope: VecDeque::with_capacity(capacity),
+            capacity,
+            hop: 0,
+            last_tempo_hop: 0,
+            period: None,
+            next_beat: 0.0,
+            bpm: 0.0,
+            confidence: 0.0,
+        }
+    }
+
+    /// Process one hop of samples.
+    pub(crate) fn process(&mut self, samples: &[f32]) -> Update {
+        let flux = self.spectral_flux(samples);
+        if self.envelope.len() == self.capacity {
+            self.envelope.pop_front();
+        }
+        self.envelope.push_back(flux);
+        self.hop += 1;
+
+        if (self.hop - self.last_tempo_hop) as f64 >= TEMPO_INTERVAL * self.rate
+            && self.envelope.len() * 2 >= self.capacity
+        {
+            self.last_tempo_hop = self.hop;
+            self.estimate_tempo();
+        }
+
+        let beat = self.track_beat(self.is_onset());
+        Update {
+            bpm: self.bpm,
+            confidence: self.confidence,
+            beat,
+        }
+    }
+
+    /// Sum of positive changes in log-compressed magnitude since the last hop.
+    fn spectral_flux(&mut self, samples: &[f32]) -> f64 {
+        let spectrum = self.fft.push(samples);
+        let mut flux = 0.0;
+        for (prev, c) in self.prev_magnitudes.iter_mut().zip(spectrum) {
+            let magnitude = (1.0 + 100.0 * c.norm()).ln();
+            flux += (magnitude - *prev).max(0.0) as f64;
+            *prev = magnitude;
+        }
+        flux
+    }
+
+    /// Whether the previous hop was a local flux peak above the adaptive
+    /// threshold. Peaks are confirmed one hop late.
+    fn is_onset(&self) -> bool {
+        let len = self.envelope.len();
+        if len < 3 {
+            return false;
+        }
+        let window = ((THRESHOLD_SECS * self.rate) as usize).clamp(1, len);
+        let mean = self.envelope.iter().rev().take(window).sum::<f64>() / window as f64;
+        let (before, peak, after) = (
+            self.envelope[len - 3],
+            self.envelope[len - 2],
+            self.envelope[len - 1],
+        );
+        peak > before && peak >= after && peak > mean * THRESHOLD_RATIO
+    }
+
+    /// Autocorrelate the onset envelope over the BPM range, weighted by a
+    /// log-normal prior around `PRIOR_BPM` to avoid octave errors.
+    fn estimate_tempo(&mut self) {
+        let mean = self.envelope.iter().sum::<f64>() / self.envelope.len() as f64;
+        let env: Vec<f64> = self.envelope.iter().map(|v| v - mean).collect();
+        let energy: f64 = env.iter().map(|v| v * v).sum();
+        if energy <= f64::EPSILON {
+            self.confidence = 0.0;
+            return;
+        }
+
+        let min_lag = (60.0 * self.rate / MAX_BPM).floor().max(1.0) as usize;
+        let max_lag = ((60.0 * self.rate / MIN_BPM).ceil() as usize).min(env.len() - 1);
+        if min_lag + 2 > max_lag {
+            return;
+        }
+        let acf: Vec<f64> = (min_lag - 1..=max_lag + 1)
+            .map(|lag| {
+                let sum: f64 = env.iter().zip(&env[lag..]).map(|(a, b)| a * b).sum();
+                // Unbias for the shrinking overlap.
+                sum * env.len() as f64 / (env.len() - lag) as f64 / energy
+            })
+            .collect();
+
+        let mut best = None;
+        for i in 1..acf.len() - 1 {
+            let lag = (min_lag - 1 + i) as f64;
+            let bpm = 60.0 * self.rate / lag;
+            let prior = (-0.5 * (bpm / PRIOR_BPM).log2().powi(2) / 0.5f64.powi(2)).exp();
+            let score = acf[i] * prior;
+            if best.is_none_or(|(_, s)| score > s) {
+                best = Some((i, score));
+            }
+        }
+        let Some((i, _)) = best else {
+            return;
+        };
+
+        // Parabolic interpolation around the peak for sub-hop precision.
+        let (a, b, c) = (acf[i - 1], acf[i], acf[i + 1]);
+        let denom = a - 2.0 * b + c;
+        let offset = if denom.abs() > f64::EPSILON {
+            (0.5 * (a - c) / denom).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        };
+        let period = (min_lag - 1 + i) as f64 + offset;
+
+        self.confidence = b.clamp(0.0, 1.0);
+        if self.confidence < MIN_CONFIDENCE {
+            return;
+        }
+        let bpm = 60.0 * self.rate / period;
+        // Smooth small drifts but jump on real tempo changes.
+        self.bpm = if self.bpm > 0.0 && (bpm - self.bpm).abs() / self.bpm < 0.05 {
+            self.bpm * 0.8 + bpm * 0.2
+        } else {
+            bpm
+        };
+        if self.period.is_none() {
+            self.next_beat = self.hop as f64;
+        }
+        self.period = Some(60.0 * self.rate / self.bpm);
+    }
+
+    /// Advance the beat clock, snapping its phase to onsets close to the
+    /// predicted beat. Returns whether a beat falls on this hop.
+    fn track_beat(&mut self, onset: bool) -> bool {
+        let Some(period) = self.period else {
+            return false;
+        };
+        if self.confidence < MIN_CONFIDENCE {
+            return false;
+        }
+        // The onset was detected one hop late.
+        let now = self.hop as f64;
+        let onset_time = now - 1.0;
+
+        if onset && (onset_time - self.next_beat).abs() <= period * PHASE_TOLERANCE {
+            self.next_beat = onset_time + period;
+            return true;
+        }
+        if now >= self.next_beat + period * PHASE_TOLERANCE {
+            // No onset near the prediction; keep the pulse going, skipping
+            // any beats missed while confidence was low.
+            while self.next_beat + period * PHASE_TOLERANCE <= now {
+                self.next_beat += period;
+            }
+            return true;
+        }
+        false
+    }
+}
+
+pub fn register() {
+    cxx_qt::qml_register_type::<BeatTracker>("Vela", 1, 0, "BeatTracker");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_collector::SampleBuffer;
+
+    const RATE: u32 = 44_100;
+    const HOP: usize = 512;
+
+    /// A click track as 16-bit PCM, as read from a WAV file: a 10 ms burst
+    /// of decaying noise on every beat.
+    fn click_track(bpm: f64, secs: f64) -> Vec<i16> {
+        let len = (secs * RATE as f64) as usize;
+        let beat = 60.0 / bpm * RATE as f64;
+        let click = RATE as usize / 100;
+        let mut seed = 0x2545_f491_u32;
+        let mut pcm = vec![0i16; len];
+        let mut next = 0.0;
+        while (next as usize) < len {
+            let start = next as usize;
+            for (i, s) in pcm[start..(start + click).min(len)].iter_mut().enumerate() {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let noise = (seed >> 16) as f64 / 32768.0 - 1.0;
+                let decay = 1.0 - i as f64 / click as f64;
+                *s = (noise * decay * 20_000.0) as i16;
+            }
+            next += beat;
+        }
+        pcm
+    }
+
+    /// Feed `pcm` through a collector buffer in chunks, as `loadChunk` and
+    /// the worker do, and return the detector with the hops beats fell on.
+    fn run(pcm: &[i16]) -> (BeatDetector, Vec<u64>) {
+        let buffer = SampleBuffer::new(HOP * 4);
+        let mut detector = BeatDetector::new(RATE, HOP);
+        let mut cursor = buffer.position();
+        let mut pending = Vec::new();
+        let mut beats = Vec::new();
+        for chunk in pcm.chunks(HOP) {
+            buffer.push_i16(chunk);
+            buffer.read_new(&mut cursor, &mut pending);
+            while pending.len() >= HOP {
+                if detector.process(&pending[..HOP]).beat {
+                    beats.push(detector.hop);
+                }
+                pending.drain(..HOP);
+            }
+        }
+        (detector, beats)
+    }
+
+    fn assert_tracks(bpm: f64) {
+        let (detector, beats) = run(&click_track(bpm, 16.0));
+        assert!((detector.bpm - bpm).abs() < 2.0, "{} BPM read as {}", bpm, detector.bpm);
+        assert!(detector.confidence >= MIN_CONFIDENCE);
+
+        // Once locked on, beats should be one period apart.
+        let period = 60.0 / bpm * RATE as f64 / HOP as f64;
+        let settled: Vec<u64> = beats
+            .into_iter()
+            .filter(|&hop| hop as f64 > 10.0 * RATE as f64 / HOP as f64)
+            .collect();
+        assert!(settled.len() >= 8, "only {} beats tracked", settled.len());
+        for pair in settled.windows(2) {
+            let spacing = (pair[1] - pair[0]) as f64;
+            assert!(
+                (spacing - period).abs() <= 2.0,
+                "beats {spacing} hops apart, expected {period}"
+            );
+        }
+    }
+
+    #[test]
+    fn tracks_120_bpm() {
+        assert_tracks(120.0);
+    }
+
+    #[test]
+    fn tracks_90_bpm() {
+        assert_tracks(90.0);
+    }
+
+    #[test]
+    fn tracks_150_bpm() {
+        assert_tracks(150.0);
+    }
+
+    #[test]
+    fn silence_has_no_tempo() {
+        let (detector, beats) = run(&vec![0; RATE as usize * 10]);
+        assert_eq!(detector.bpm, 0.0);
+        assert!(beats.is_empty());
+    }
+}
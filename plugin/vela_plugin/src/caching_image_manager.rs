@@ -0,0 +1,202 @@
+use crossbeam_channel::{unbounded, Sender};
+use cxx_qt::{CxxQtThread, QObject, Threading};
+use qt6_core::{QString, QUrl};
+use qt6_quick::QQuickItem;
+
+use std::path::PathBuf;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, OnceLock,
+};
+use std::thread;
+
+use crate::image_cache;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Shared decode/resize workers, one per core.
+fn pool() -> &'static Sender<Job> {
+    static POOL: OnceLock<Sender<Job>> = OnceLock::new();
+    POOL.get_or_init(|| {
+        let (tx, rx) = unbounded::<Job>();
+        let workers = thread::available_parallelism().map_or(2, |n| n.get());
+        for i in 0..workers {
+            let rx = rx.clone();
+            thread::Builder::new()
+                .name(format!("vela-imagecache-{i}"))
+                .spawn(move || {
+                    while let Ok(job) = rx.recv() {
+                        job();
+                    }
+                })
+                .expect("Failed to spawn image cache worker");
+        }
+        tx
+    })
+}
+
+#[derive(QObject)]
+pub struct CachingImageManager {
+    /// The item the image is displayed in; its size picks the thumbnail bucket.
+    #[qproperty]
+    item: *mut QQuickItem,
+
+    #[qproperty(cpp_name = "cacheDir")]
+    cache_dir: QUrl,
+
+    #[qproperty]
+    path: QString,
+
+    #[qproperty(read, cpp_name = "cachePath", notify = "cachePathChanged")]
+    cache_path: QUrl,
+
+    /// Cap on the total size of the thumbnail cache in bytes. Least recently
+    /// used thumbnails are evicted once it is exceeded.
+    #[qproperty(cpp_name = "maxCacheSize")]
+    max_cache_size: u64,
+
+    /// Bumped per request so results for a previous path are dropped.
+    generation: Arc<AtomicU64>,
+}
+
+impl Default for CachingImageManager {
+    fn default() -> Self {
+        Self {
+            item: std::ptr::null_mut(),
+            cache_dir: QUrl::default(),
+            path: QString::default(),
+            cache_path: QUrl::default(),
+            max_cache_size: image_cache::DEFAULT_MAX_BYTES,
+            generation: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl CachingImageManager {
+    #[qproperty(cpp_name = "item")]
+    pub fn set_item(&mut self, item: *mut QQuickItem) {
+        if self.item == item {
+            return;
+        }
+        self.item = item;
+        self.itemChanged();
+        self.update_source();
+    }
+
+    #[qproperty(cpp_name = "cacheDir")]
+    pub fn set_cache_dir(&mut self, dir: &QUrl) {
+        if &self.cache_dir == dir {
+            return;
+        }
+        self.cache_dir = dir.clone();
+        self.cacheDirChanged();
+        self.update_source();
+    }
+
+    #[qproperty(cpp_name = "path")]
+    pub fn set_path(&mut self, path: &QString) {
+        if &self.path == path {
+            return;
+        }
+        self.path = path.clone();
+        self.pathChanged();
+        self.update_source();
+    }
+
+    /// Resolve `path` to a cached thumbnail matching the item's current pixel
+    /// size. Decoding happens on the worker pool; `cachePath` is updated once
+    /// the thumbnail is ready.
+    #[qinvokable(cpp_name = "updateSource")]
+    pub fn update_source(&mut self) {
+        let current = self.generation.fetch_add(1, Ordering::AcqRel) + 1;
+        let source = PathBuf::from(self.path.to_string());
+        if source.as_os_str().is_empty() || self.item.is_null() {
+            self.set_cache_path(QUrl::default());
+            return;
+        }
+
+        // Images larger than the biggest bucket are shown as-is.
+        let Some((bucket, size)) = image_cache::bucket_for(self.target_size()) else {
+            self.set_cache_path(QUrl::from_local_file(&self.path));
+            return;
+        };
+        let cache_dir = PathBuf::from(self.cache_dir.to_local_file().to_string());
+        let max_bytes = self.max_cache_size;
+
+        let generation = self.generation.clone();
+        let qt_thread = self.qt_thread();
+        let _ = pool().send(Box::new(move || {
+            if generation.load(Ordering::Acquire) != current {
+                return;
+            }
+            let thumb = image_cache::ensure_thumbnail(&source, &cache_dir.join(bucket), size);
+            let url = match thumb {
+                Ok(thumb) => {
+                    QUrl::from_local_file(&QString::from(thumb.to_string_lossy().as_ref()))
+                }
+                Err(e) => {
+                    eprintln!("CachingImageManager: {}: {e}", source.display());
+                    QUrl::from_local_file(&QString::from(source.to_string_lossy().as_ref()))
+                }
+            };
+            queue_cache_path(&qt_thread, generation, current, url);
+            if image_cache::should_sweep() {
+                image_cache::sweep(&cache_dir, max_bytes);
+            }
+        }));
+    }
+
+    /// Delete every thumbnail in `cacheDir` on a worker thread, then
+    /// re-resolve `path`.
+    #[qinvokable]
+    pub fn purge(&self) {
+        let cache_dir = PathBuf::from(self.cache_dir.to_local_file().to_string());
+        if cache_dir.as_os_str().is_empty() {
+            return;
+        }
+        let qt_thread = self.qt_thread();
+        let _ = pool().send(Box::new(move || {
+            image_cache::purge(&cache_dir);
+            let _ = qt_thread.queue(|manager: &mut CachingImageManager| manager.update_source());
+        }));
+    }
+
+    /// Largest side of the item in device pixels.
+    fn target_size(&self) -> u32 {
+        unsafe {
+            let item = &*self.item;
+            let dpr = item
+                .window()
+                .map_or(1.0, |w| w.effective_device_pixel_ratio());
+            (item.width().max(item.height()) * dpr).ceil() as u32
+        }
+    }
+
+    fn set_cache_path(&mut self, url: QUrl) {
+        if self.cache_path == url {
+            return;
+        }
+        self.cache_path = url;
+        self.cachePathChanged();
+    }
+
+    #[cxx_qt::qsignal]
+    fn cachePathChanged(&self);
+}
+
+fn queue_cache_path(
+    qt_thread: &CxxQtThread<CachingImageManager>,
+    generation: Arc<AtomicU64>,
+    current: u64,
+    url: QUrl,
+) {
+    let _ = qt_thread.queue(move |manager: &mut CachingImageManager| {
+        if generation.load(Ordering::Acquire) == current {
+            manager.set_cache_path(url);
+        }
+    });
+}
+
+pub fn register() {
+    cxx_qt::qml_register_type::<CachingImageManager>("Vela", 1, 0, "CachingImageManager");
+}
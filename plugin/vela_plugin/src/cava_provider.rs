@@ -0,0 +1,310 @@
+use cxx_qt::{CxxQtThread, QObject, Threading};
+use qt6_core::QVector;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::analyser::{Analyser, SlidingFft, Worker};
+use crate::audio_collector::AudioCollector;
+
+/// FFT length. 4096 gives ~11 Hz bins at 44.1 kHz, enough to separate bass bars.
+const FFT_SIZE: usize = 4096;
+
+/// Frequency range covered by the bars (Hz).
+const LOW_CUTOFF: f64 = 50.0;
+const HIGH_CUTOFF: f64 = 10_000.0;
+
+/// Per-frame decay applied to falling bars.
+const FALLOFF: f64 = 0.77;
+
+/// Autosensitivity: shrink quickly on overshoot, grow slowly otherwise.
+const SENS_DECREASE: f64 = 0.98;
+const SENS_INCREASE: f64 = 1.002;
+const SENS_MIN: f64 = 0.02;
+const SENS_MAX: f64 = 50.0;
+
+/// Levels below this are treated as silence and do not raise the sensitivity.
+const SILENCE: f64 = 1e-6;
+
+#[derive(QObject)]
+#[qobject(base = "Service")]
+pub struct CavaProvider {
+    #[qproperty]
+    collector: *mut AudioCollector,
+
+    /// Number of output bars.
+    #[qproperty]
+    bars: i32,
+
+    /// Updates per second.
+    #[qproperty]
+    framerate: u32,
+
+    /// Strength of the monstercat smoothing filter; 0 disables it.
+    #[qproperty]
+    monstercat: f64,
+
+    #[qproperty(read, notify = "valuesChanged")]
+    values: QVector<f64>,
+
+    worker: Worker,
+}
+
+impl Default for CavaProvider {
+    fn default() -> Self {
+        let bars = 45;
+        Self {
+            collector: std::ptr::null_mut(),
+            bars,
+            framerate: 60,
+            monstercat: 1.5,
+            // Filled up front so readers can index before the first frame.
+            values: QVector::from(vec![0.0; bars as usize]),
+            worker: Worker::default(),
+        }
+    }
+}
+
+impl CavaProvider {
+    #[qproperty(cpp_name = "collector")]
+    pub fn set_collector(&mut self, collector: *mut AudioCollector) {
+        self.switch_collector(collector);
+    }
+
+    /// Setter for bars; resets values and restarts if running.
+    #[qproperty(cpp_name = "bars")]
+    pub fn set_bars(&mut self, bars: i32) {
+        let bars = bars.max(1);
+        if self.bars == bars {
+            return;
+        }
+        self.bars = bars;
+        self.barsChanged();
+        self.set_values(QVector::from(vec![0.0; bars as usize]));
+        self.restart();
+    }
+
+    /// Setter for framerate; restarts if running.
+    #[qproperty(cpp_name = "framerate")]
+    pub fn set_framerate(&mut self, framerate: u32) {
+        let framerate = framerate.clamp(1, 240);
+        if self.framerate == framerate {
+            return;
+        }
+        self.framerate = framerate;
+        self.framerateChanged();
+        self.restart();
+    }
+
+    /// Setter for monstercat; restarts if running.
+    #[qproperty(cpp_name = "monstercat")]
+    pub fn set_monstercat(&mut self, monstercat: f64) {
+        let monstercat = monstercat.max(0.0);
+        if self.monstercat == monstercat {
+            return;
+        }
+        self.monstercat = monstercat;
+        self.monstercatChanged();
+        self.restart();
+    }
+
+    fn set_values(&mut self, values: QVector<f64>) {
+        if self.values == values {
+            return;
+        }
+        self.values = values;
+        self.valuesChanged(&self.values);
+    }
+
+    #[cxx_qt::qsignal]
+    fn valuesChanged(&self, values: &QVector<f64>);
+
+    /// Called by `Service` when the first reference is taken. Spawns the
+    /// analyser thread, which checks the collector for new samples at
+    /// `framerate` and only updates when some arrived.
+    #[cxx_qt::cxx_override]
+    pub fn start(&mut self) {
+        let bars = self.bars as usize;
+        let frame = Duration::from_secs_f64(1.0 / self.framerate as f64);
+        let monstercat = self.monstercat;
+        let qt_thread = self.qt_thread();
+        self.worker.start(self.collector, move |source| {
+            let samples = source.samples.as_ref();
+            let mut spectrum = Spectrum::new(bars, source.sample_rate, monstercat);
+            let mut cursor = samples.position();
+            let mut fresh = Vec::new();
+            let mut next = Instant::now();
+            while !source.stopped() {
+                fresh.clear();
+                // Feeding the same chunk twice would make the signal
+                // discontinuous, so frames without new samples are skipped.
+                if samples.read_new(&mut cursor, &mut fresh) > 0 {
+                    let values = spectrum.process(&fresh);
+                    if !queue_values(&qt_thread, values) {
+                        return;
+                    }
+                }
+                next += frame;
+                match next.checked_duration_since(Instant::now()) {
+                    Some(wait) => thread::sleep(wait),
+                    // Fell behind; don't try to catch up with a burst.
+                    None => next = Instant::now(),
+                }
+            }
+        });
+    }
+
+    /// Called by `Service` when the last reference is dropped.
+    #[cxx_qt::cxx_override]
+    pub fn stop(&mut self) {
+        self.worker.stop();
+    }
+}
+
+impl Analyser for CavaProvider {
+    fn worker(&mut self) -> &mut Worker {
+        &mut self.worker
+    }
+
+    fn collector_mut(&mut self) -> &mut *mut AudioCollector {
+        &mut self.collector
+    }
+
+    fn collector_changed(&self) {
+        self.collectorChanged();
+    }
+
+    fn start(&mut self) {
+        CavaProvider::start(self);
+    }
+}
+
+fn queue_values(qt_thread: &CxxQtThread<CavaProvider>, values: Vec<f64>) -> bool {
+    qt_thread
+        .queue(move |provider: &mut CavaProvider| {
+            provider.set_values(QVector::from(values));
+        })
+        .is_ok()
+}
+
+/// Bar spectrum analyser: Hann-windowed FFT, log-spaced bars, falloff,
+/// monstercat smoothing and autosensitivity.
+pub(crate) struct Spectrum {
+    fft: SlidingFft,
+    /// FFT bin range `[start, end)` for each bar.
+    ranges: Vec<(usize, usize)>,
+    /// Per-bar gain compensating for the falling energy of higher bands.
+    eq: Vec<f64>,
+    levels: Vec<f64>,
+    sensitivity: f64,
+    monstercat: f64,
+}
+
+impl Spectrum {
+    pub(crate) fn new(bars: usize, sample_rate: u32, monstercat: f64) -> Self {
+        let bin_width = sample_rate as f64 / FFT_SIZE as f64;
+        let max_bin = FFT_SIZE / 2;
+        let high = HIGH_CUTOFF.min(sample_rate as f64 / 2.0);
+
+        // Log-spaced edges, forced to advance by at least one bin per bar.
+        let mut edges = Vec::with_capacity(bars + 1);
+        for i in 0..=bars {
+            let freq = LOW_CUTOFF * (high / LOW_CUTOFF).powf(i as f64 / bars as f64);
+            let mut bin = ((freq / bin_width).round() as usize).clamp(1, max_bin);
+            if let Some(&prev) = edges.last() {
+                bin = bin.max(prev + 1).min(max_bin);
+            }
+            edges.push(bin);
+        }
+        let ranges: Vec<_> = edges
+            .windows(2)
+            .map(|w| (w[0], w[1].max(w[0] + 1).min(max_bin + 1)))
+            .collect();
+        let eq = ranges
+            .iter()
+            .map(|&(start, end)| {
+                let centre = (start + end) as f64 / 2.0 * bin_width;
+                centre.sqrt() / (FFT_SIZE as f64).log2()
+            })
+            .collect();
+
+        Self {
+            fft: SlidingFft::new(FFT_SIZE),
+            ranges,
+            eq,
+            levels: vec![0.0; bars],
+            sensitivity: 1.0,
+            monstercat,
+        }
+    }
+
+    /// Feed the latest samples and return the bar levels in `[0, 1]`.
+    pub(crate) fn process(&mut self, samples: &[f32]) -> Vec<f64> {
+        let spectrum = self.fft.push(samples);
+        let mut raw: Vec<f64> = self
+            .ranges
+            .iter()
+            .zip(&self.eq)
+            .map(|(&(start, end), &eq)| {
+                let bins = &spectrum[start..end.min(FFT_SIZE / 2)];
+                let sum: f64 = bins.iter().map(|c| c.norm() as f64).sum();
+                sum / bins.len().max(1) as f64 * eq * self.sensitivity
+            })
+            .collect();
+
+        if self.monstercat > 0.0 {
+            monstercat_filter(&mut raw, self.monstercat);
+        }
+
+        let mut overshoot = false;
+        let mut silent = true;
+        for (level, new) in self.levels.iter_mut().zip(raw) {
+            *level = new.max(*level * FALLOFF);
+            if *level > 1.0 {
+                overshoot = true;
+                *level = 1.0;
+            }
+            if new > SILENCE {
+                silent = false;
+            }
+        }
+
+        if overshoot {
+            self.sensitivity *= SENS_DECREASE;
+        } else if !silent {
+            self.sensitivity *= SENS_INCREASE;
+        }
+        self.sensitivity = self.sensitivity.clamp(SENS_MIN, SENS_MAX);
+
+        self.levels.clone()
+    }
+}
+
+/// Let each bar raise its neighbours to at least its own height divided by
+/// `strength` per bar of distance, giving the smooth "monstercat" look.
+fn monstercat_filter(bars: &mut [f64], strength: f64) {
+    let strength = strength.max(1.0 + f64::EPSILON);
+    let len = bars.len();
+    for z in 0..len {
+        let mut falloff = bars[z];
+        for m in (0..z).rev() {
+            falloff /= strength;
+            if falloff <= bars[m] {
+                break;
+            }
+            bars[m] = falloff;
+        }
+        let mut falloff = bars[z];
+        for bar in bars.iter_mut().skip(z + 1) {
+            falloff /= strength;
+            if falloff <= *bar {
+                break;
+            }
+            *bar = falloff;
+        }
+    }
+}
+
+pub fn register() {
+    cxx_qt::qml_register_type::<CavaProvider>("Vela", 1, 0, "CavaProvider");
+}
@@ -1,98 +1,811 @@
 use color_thief::{get_color, ColorFormat};
-use cxx_qt::QObject;
-use image::{self, imageops::FilterType, GenericImageView};
-use qt6_core::{QColor, QUrl};
+use cxx_qt::{QObject, Threading};
+use image::{
+    self, imageops::FilterType, DynamicImage, GenericImageView, ImageFormat, RgbaImage,
+};
+use qt6_core::{
+    QByteArray, QColor, QRectF, QString, QUrl, QVariant, QVariantList, QVariantMap,
+};
+use qt6_gui::QImageFormat;
+use qml6::QJSValue;
+use qt6_quick::QQuickItem;
+
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::file_ops;
+use crate::image_loader::{self, LoadError};
+use crate::palette::{self, Palette};
+use crate::trash;
+use crate::url;
+
+/// Frames sampled from an animation for colour analysis.
+const ANALYSIS_FRAMES: usize = 8;
+
+/// Minimum time between `copyProgress` signals.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(50);
 
 #[derive(QObject, Default)]
 pub struct CUtils;
 
 impl CUtils {
-    /// We will copy a file from the source to our target. This returns true on sucess.
+    /// We will copy a file from the source to our target, replacing it if it
+    /// exists. Returns `{ ok, error, message }`; see `file_ops::result_to_map`.
     #[qinvokable(cpp_name = "copyFile")]
-    pub fn copy_file(&self, source: &QUrl, target: &QUrl, overwrite: bool) -> bool {
-        use std::fs;
-        let src = source.to_string();
-        let dst = target.to_string();
-        if overwrite {
-            let _ = std::fs::remove_file(&dst);
-        }
-        fs::copy(src, dst).is_ok()
+    pub fn copy_file(&self, source: &QUrl, target: &QUrl) -> QVariantMap {
+        self.copy_file_overwrite(source, target, true)
     }
 
-    /// Deletes the specified file at a given path.
+    /// `copyFile` with explicit overwrite. Without `overwrite` an existing
+    /// target is left alone and the result's `error` is `"exists"`.
+    #[qinvokable(cpp_name = "copyFile")]
+    pub fn copy_file_overwrite(
+        &self,
+        source: &QUrl,
+        target: &QUrl,
+        overwrite: bool,
+    ) -> QVariantMap {
+        let src = local_path(source);
+        let dst = local_path(target);
+        file_ops::result_to_map(&file_ops::copy_file(&src, &dst, overwrite))
+    }
+
+    /// Deletes the specified file at a given path. Returns
+    /// `{ ok, error, message }` like `copyFile`.
     #[qinvokable(cpp_name = "deleteFile")]
-    pub fn delete_file(&self, path: &QUrl) -> bool {
-        std::fs::remove_file(path.to_string()).is_ok()
+    pub fn delete_file(&self, path: &QUrl) -> QVariantMap {
+        let path = local_path(path);
+        file_ops::result_to_map(&file_ops::delete_file(&path))
+    }
+
+    /// Moves the file or directory at `path` to the trash instead of
+    /// deleting it. Returns `{ ok, error, message, id }`, where `id` can be
+    /// passed to `restoreFromTrash` to undo.
+    #[qinvokable(cpp_name = "trashFile")]
+    pub fn trash_file(&self, path: &QUrl) -> QVariantMap {
+        let path = local_path(path);
+        let result = trash::trash(&path);
+        let mut map = file_ops::result_to_map(&result);
+        if let Ok(id) = result {
+            map.insert("id", QVariant::from(&QString::from(id.to_string_lossy().as_ref())));
+        }
+        map
+    }
+
+    /// Puts a trashed file back at its original location. Returns
+    /// `{ ok, error, message, path }`; `error` is `"exists"` if something
+    /// now occupies that location.
+    #[qinvokable(cpp_name = "restoreFromTrash")]
+    pub fn restore_from_trash(&self, id: &QString) -> QVariantMap {
+        let result = trash::restore(Path::new(&id.to_string()));
+        let mut map = file_ops::result_to_map(&result);
+        if let Ok(path) = result {
+            map.insert("path", QVariant::from(&QString::from(path.to_string_lossy().as_ref())));
+        }
+        map
+    }
+
+    /// Everything in the trash, most recently deleted first, as a list of
+    /// `{ id, name, originalPath, deletionDate, isDir }`.
+    #[qinvokable(cpp_name = "listTrash")]
+    pub fn list_trash(&self) -> QVariantList {
+        let mut list = QVariantList::default();
+        for entry in trash::list() {
+            let name = entry
+                .original_path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let date = entry
+                .deletion_date
+                .map(|d| d.format("%Y-%m-%dT%H:%M:%S").to_string())
+                .unwrap_or_default();
+            let mut map = QVariantMap::default();
+            map.insert("id", QVariant::from(&QString::from(entry.id.to_string_lossy().as_ref())));
+            map.insert("name", QVariant::from(&QString::from(name)));
+            map.insert(
+                "originalPath",
+                QVariant::from(&QString::from(entry.original_path.to_string_lossy().as_ref())),
+            );
+            map.insert("deletionDate", QVariant::from(&QString::from(date)));
+            map.insert("isDir", QVariant::from(&entry.is_dir));
+            list.append(QVariant::from(&map));
+        }
+        list
+    }
+
+    /// Writes `data` to `path` atomically: readers such as `FileView` see
+    /// either the old file or the complete new one, never a partial write.
+    /// Returns `{ ok, error, message }` like `copyFile`.
+    #[qinvokable(cpp_name = "writeFileAtomic")]
+    pub fn write_file_atomic(&self, path: &QUrl, data: &QByteArray) -> QVariantMap {
+        let path = local_path(path);
+        file_ops::result_to_map(&file_ops::write_atomic(&path, data.as_slice()))
+    }
+
+    /// `writeFileAtomic` for text, written as UTF-8.
+    #[qinvokable(cpp_name = "writeFileAtomic")]
+    pub fn write_text_atomic(&self, path: &QUrl, text: &QString) -> QVariantMap {
+        let path = local_path(path);
+        file_ops::result_to_map(&file_ops::write_atomic(&path, text.to_string().as_bytes()))
+    }
+
+    /// Moves a file or directory, copying across filesystems when a rename
+    /// is not possible. Without `overwrite` an existing target is left alone
+    /// and `error` is `"exists"`.
+    #[qinvokable(cpp_name = "moveFile")]
+    pub fn move_file(&self, source: &QUrl, target: &QUrl, overwrite: bool) -> QVariantMap {
+        let src = local_path(source);
+        let dst = local_path(target);
+        file_ops::result_to_map(&file_ops::move_path(&src, &dst, overwrite))
+    }
+
+    /// Creates `path` and any missing parent directories.
+    #[qinvokable(cpp_name = "makeDirs")]
+    pub fn make_dirs(&self, path: &QUrl) -> QVariantMap {
+        let path = local_path(path);
+        file_ops::result_to_map(&file_ops::make_dirs(&path))
     }
 
-    /// Converts a QUrl to a local file path (removes the "file://" prefix).
+    /// Recursively copies the directory `source` to `target` on a worker
+    /// thread. Returns a job id; `copyProgress` reports bytes copied as it
+    /// goes and `copyFinished` carries the `{ ok, error, message }` result.
+    #[qinvokable(cpp_name = "copyDir")]
+    pub fn copy_dir(&self, source: &QUrl, target: &QUrl, overwrite: bool) -> i32 {
+        static NEXT_JOB: AtomicI32 = AtomicI32::new(1);
+        let job = NEXT_JOB.fetch_add(1, Ordering::Relaxed);
+        let src = local_path(source);
+        let dst = local_path(target);
+        let qt_thread = self.qt_thread();
+        thread::spawn(move || {
+            let mut last = Instant::now() - PROGRESS_INTERVAL;
+            let result = file_ops::copy_dir(&src, &dst, overwrite, |copied, total, file| {
+                if copied < total && last.elapsed() < PROGRESS_INTERVAL {
+                    return;
+                }
+                last = Instant::now();
+                let file = QString::from(file.to_string_lossy().as_ref());
+                let _ = qt_thread.queue(move |cutils: &mut CUtils| {
+                    cutils.copyProgress(job, copied as f64, total as f64, &file);
+                });
+            });
+            let _ = qt_thread.queue(move |cutils: &mut CUtils| {
+                cutils.copyFinished(job, &file_ops::result_to_map(&result));
+            });
+        });
+        job
+    }
+
+    /// Emitted during `copyDir` at most every `PROGRESS_INTERVAL`, and once
+    /// the last file is copied. Byte counts are doubles to survive QML.
+    #[cxx_qt::qsignal]
+    fn copyProgress(&self, job: i32, copied: f64, total: f64, file: &QString);
+
+    #[cxx_qt::qsignal]
+    fn copyFinished(&self, job: i32, result: &QVariantMap);
+
+    /// Converts a QUrl to a local file path: percent-escapes are decoded,
+    /// `file://localhost/` and `file://<this host>/` are accepted and a
+    /// leading `~` is expanded. Returns an empty string for non-local URLs.
     #[qinvokable(cpp_name = "toLocalFile")]
     pub fn to_local_file(&self, url: &QUrl) -> String {
-        let s = url.to_string();
-        s.strip_prefix("file://").unwrap_or(&s).to_string()
+        local_path(url).to_string_lossy().into_owned()
+    }
+
+    /// Grabs `item` and saves it to `path`. See `save_item_rect_callback`.
+    #[qinvokable(cpp_name = "saveItem")]
+    pub fn save_item(&self, item: *mut QQuickItem, path: &QUrl) {
+        self.save_item_rect_callback(item, path, &QRectF::default(), QJSValue::default());
+    }
+
+    /// Grabs `item`, crops it to `rect` and saves it to `path`.
+    #[qinvokable(cpp_name = "saveItem")]
+    pub fn save_item_rect(&self, item: *mut QQuickItem, path: &QUrl, rect: &QRectF) {
+        self.save_item_rect_callback(item, path, rect, QJSValue::default());
+    }
+
+    /// Grabs `item`, crops it to `rect` (in item coordinates; an empty rect
+    /// keeps the whole item) and saves it to `path`. The format follows the
+    /// extension: PNG, JPEG, WebP or QOI. Encoding runs on a worker thread and
+    /// `callback`, if callable, is invoked with the written path afterwards,
+    /// or with `null` and the error message if saving failed.
+    #[qinvokable(cpp_name = "saveItem")]
+    pub fn save_item_rect_callback(
+        &self,
+        item: *mut QQuickItem,
+        path: &QUrl,
+        rect: &QRectF,
+        callback: QJSValue,
+    ) {
+        let Some(item) = (unsafe { item.as_mut() }) else {
+            deliver_saved(&callback, Err("a valid item is required".to_string()));
+            return;
+        };
+        let path = local_path(path);
+        if path.as_os_str().is_empty() {
+            deliver_saved(&callback, Err("a local file path is required".to_string()));
+            return;
+        }
+        let rect = rect.clone();
+        let qt_thread = self.qt_thread();
+        grab_item(item, move |grabbed| {
+            let (img, dpr) = match grabbed {
+                Ok(grabbed) => grabbed,
+                Err(e) => {
+                    let _ = qt_thread.queue(move |_: &mut CUtils| deliver_saved(&callback, Err(e)));
+                    return;
+                }
+            };
+            thread::spawn(move || {
+                let saved = crop_to_rect(img, &rect, dpr)
+                    .and_then(|img| save_image(&img, &path))
+                    .map(|()| path.clone())
+                    .map_err(|e| format!("failed to save {}: {e}", path.display()));
+                let _ = qt_thread.queue(move |_: &mut CUtils| deliver_saved(&callback, saved));
+            });
+        });
     }
 
     /// Grabs the dominant color of a wallpaper. If `rescale_size` is None,
-    /// this defaults to 128
+    /// this defaults to 128. Animations are sampled across their frames.
+    /// Returns an invalid color if the image could not be decoded.
     #[qinvokable(cpp_name = "getDominantColor")]
     pub fn get_dominant_color(&self, path: &str, rescale_size: Option<i32>) -> QColor {
         let size = rescale_size.unwrap_or(128).max(1) as u32;
+        load(Path::new(path))
+            .ok()
+            .and_then(|frames| dominant_color(&montage(&frames, size), size))
+            .map_or_else(QColor::default, rgb_to_color)
+    }
 
-        match image::open(path) {
-            Ok(img) => {
-                /// Downscaling for performance.
-                /// Triangle here is a decent middle of the road quality/speed tradeoff.
-                let resized = img.resize(size, size, FilterType::Triangle);
+    /// Asynchronous `getDominantColor` for a file path. The image is decoded
+    /// on a worker thread and `callback` receives the color, or `null` and
+    /// an error message if decoding failed.
+    #[qinvokable(cpp_name = "getDominantColor")]
+    pub fn get_dominant_color_path(&self, path: &QString, callback: QJSValue) {
+        self.analyse_file(PathBuf::from(path.to_string()), callback, Analysis::DominantColor);
+    }
 
-                /// Converting RGB8 (drop alpha)
-                /// ColorThief works with raw RGB/RGBA buffers.
-                let rgb = resized.to_rgb8();
+    /// Asynchronous `getDominantColor` for a `file://` URL.
+    #[qinvokable(cpp_name = "getDominantColor")]
+    pub fn get_dominant_color_url(&self, url: &QUrl, callback: QJSValue) {
+        let path = local_path(url);
+        self.analyse_file(path, callback, Analysis::DominantColor);
+    }
+
+    /// Asynchronous `getDominantColor` for whatever `item` currently renders.
+    #[qinvokable(cpp_name = "getDominantColor")]
+    pub fn get_dominant_color_item(&self, item: *mut QQuickItem, callback: QJSValue) {
+        self.analyse_item(item, callback, Analysis::DominantColor);
+    }
 
-                /// Sampling every Nth pixel,
-                /// with 10 being a good speed/quality default.
-                let quality = 10usize;
+    /// Quantizes the image at `path` into at most `count` swatches. Returns a
+    /// map with `swatches` (a list of `{ color, population, weight }`, most
+    /// common first, weights summing to 1), `dominant`, and the role candidates
+    /// `vibrant`, `lightVibrant`, `darkVibrant`, `muted`, `lightMuted` and
+    /// `darkMuted` (undefined when no swatch fits).
+    #[qinvokable(cpp_name = "getPalette")]
+    pub fn get_palette(&self, path: &QString, count: i32) -> QVariantMap {
+        load(Path::new(&path.to_string()))
+            .map(|frames| {
+                let img = montage(&frames, palette::SAMPLE_SIZE);
+                palette_to_map(&palette::extract(&img, count.max(1) as usize))
+            })
+            .unwrap_or_default()
+    }
 
-                match get_color(rgb.as_raw(), ColorFormat::Rgb, quality) {
-                    Ok(c) => QColor::from_rgb(c.r as i32, c.g as i32, c.b as i32),
-                    Err(_) => QColor::from_rgb(0, 0, 0),
-                }
-            }
-            Err(_) => QColor::from_rgb(0, 0, 0),
-        }
+    /// Asynchronous `getPalette`. The image is decoded and quantized on a
+    /// worker thread and `callback` receives the map.
+    #[qinvokable(cpp_name = "getPalette")]
+    pub fn get_palette_async(&self, path: &QString, count: i32, callback: QJSValue) {
+        let analysis = Analysis::Palette(count.max(1) as usize);
+        self.analyse_file(PathBuf::from(path.to_string()), callback, analysis);
     }
 
     /// Calculating the average luminance of the wallpaper. Downscales using Triangle
     /// for efficiency then computes luminance with the standard formula:
     /// 0.2126*R + 0.7152*G + 0.0722*B
+    /// Animations are averaged over their frames. Returns NaN if the image
+    /// could not be decoded, so a failure is never mistaken for black.
     #[qinvokable(cpp_name = "getAverageLuminance")]
     pub fn get_average_luminance(&self, path: &str, rescale_size: Option<i32>) -> f64 {
         let size = rescale_size.unwrap_or(128).max(1) as u32;
-        match image::open(path) {
-            Ok(img) => {
-                let resized = img.resize(size, size, FilterType::Triangle);
-                let mut total_luma = 0f64;
-                let mut count = 0u64;
-                for pixel in resized.pixels() {
-                    let channels = pixel.2.to_rgb();
-                    let r = channels[0] as f64 / 255.0;
-                    let g = channels[1] as f64 / 255.0;
-                    let b = channels[2] as f64 / 255.0;
-                    // Luminance formula
-                    total_luma += 0.2126 * r + 0.7152 * g + 0.0722 * b;
-                    count += 1;
-                }
-                if count > 0 {
-                    total_luma / (count as f64)
-                } else {
-                    0.0
+        load(Path::new(path)).map_or(f64::NAN, |frames| {
+            mean(frames.iter().map(|img| average_luminance(img, size)))
+        })
+    }
+
+    /// Asynchronous `getAverageLuminance` for a file path. The image is
+    /// decoded on a worker thread and `callback` receives the luminance.
+    #[qinvokable(cpp_name = "getAverageLuminance")]
+    pub fn get_average_luminance_path(&self, path: &QString, callback: QJSValue) {
+        self.analyse_file(PathBuf::from(path.to_string()), callback, Analysis::Luminance);
+    }
+
+    /// Asynchronous `getAverageLuminance` for a `file://` URL.
+    #[qinvokable(cpp_name = "getAverageLuminance")]
+    pub fn get_average_luminance_url(&self, url: &QUrl, callback: QJSValue) {
+        let path = local_path(url);
+        self.analyse_file(path, callback, Analysis::Luminance);
+    }
+
+    /// Asynchronous `getAverageLuminance` for whatever `item` currently renders.
+    #[qinvokable(cpp_name = "getAverageLuminance")]
+    pub fn get_average_luminance_item(&self, item: *mut QQuickItem, callback: QJSValue) {
+        self.analyse_item(item, callback, Analysis::Luminance);
+    }
+
+    /// Perceptual luminance of the image at `path`, optionally restricted to
+    /// `region`: a rect in normalized image coordinates (0 to 1 on both
+    /// axes), e.g. the strip under the bar; an empty rect means the whole
+    /// image. Runs on a worker thread and `callback` receives
+    /// `{ luminance, lightness }`: the mean relative luminance of the
+    /// linearised sRGB pixels (0 to 1) and its CIELAB L* (0 to 100).
+    #[qinvokable(cpp_name = "getLuminance")]
+    pub fn get_luminance_path(&self, path: &QString, region: &QRectF, callback: QJSValue) {
+        let analysis = Analysis::Perceptual(Region::from_rect(region));
+        self.analyse_file(PathBuf::from(path.to_string()), callback, analysis);
+    }
+
+    /// `getLuminance` for a `file://` URL.
+    #[qinvokable(cpp_name = "getLuminance")]
+    pub fn get_luminance_url(&self, url: &QUrl, region: &QRectF, callback: QJSValue) {
+        let path = local_path(url);
+        self.analyse_file(path, callback, Analysis::Perceptual(Region::from_rect(region)));
+    }
+
+    /// `getLuminance` for whatever `item` currently renders; `region` is
+    /// normalized to the item's size.
+    #[qinvokable(cpp_name = "getLuminance")]
+    pub fn get_luminance_item(&self, item: *mut QQuickItem, region: &QRectF, callback: QJSValue) {
+        self.analyse_item(item, callback, Analysis::Perceptual(Region::from_rect(region)));
+    }
+
+    /// Decode `path` and run `analysis` on a worker thread, then hand the
+    /// result to `callback` on the Qt thread.
+    fn analyse_file(&self, path: PathBuf, callback: QJSValue, analysis: Analysis) {
+        let qt_thread = self.qt_thread();
+        thread::spawn(move || {
+            let result = analysis.run(load(&path));
+            let _ = qt_thread.queue(move |_: &mut CUtils| result.deliver(&callback));
+        });
+    }
+
+    /// Grab `item` and run `analysis` on a worker thread, then hand the
+    /// result to `callback` on the Qt thread.
+    fn analyse_item(&self, item: *mut QQuickItem, callback: QJSValue, analysis: Analysis) {
+        let Some(item) = (unsafe { item.as_mut() }) else {
+            AnalysisResult::Failed("a valid item is required".to_string()).deliver(&callback);
+            return;
+        };
+        let qt_thread = self.qt_thread();
+        grab_item(item, move |grabbed| {
+            let img = match grabbed {
+                Ok((img, _)) => img,
+                Err(e) => {
+                    let failed = AnalysisResult::Failed(e);
+                    let _ = qt_thread.queue(move |_: &mut CUtils| failed.deliver(&callback));
+                    return;
                 }
+            };
+            thread::spawn(move || {
+                let result = analysis.run(Ok(vec![DynamicImage::ImageRgba8(img)]));
+                let _ = qt_thread.queue(move |_: &mut CUtils| result.deliver(&callback));
+            });
+        });
+    }
+}
+
+/// Colour analyses available asynchronously.
+#[derive(Clone, Copy)]
+enum Analysis {
+    DominantColor,
+    Luminance,
+    /// Palette with at most this many swatches.
+    Palette(usize),
+    /// Linear-light luminance and L* over an optional region.
+    Perceptual(Option<Region>),
+}
+
+/// Normalized `[x, y, width, height]` sub-rectangle of an image.
+#[derive(Clone, Copy)]
+struct Region([f64; 4]);
+
+impl Region {
+    fn from_rect(rect: &QRectF) -> Option<Self> {
+        if rect.is_empty() {
+            return None;
+        }
+        Some(Self([rect.x(), rect.y(), rect.width(), rect.height()]))
+    }
+
+    /// Crop `img` to the region, keeping at least one pixel.
+    fn crop(self, img: &DynamicImage) -> DynamicImage {
+        let [x, y, w, h] = self.0;
+        let (iw, ih) = (img.width() as f64, img.height() as f64);
+        let px = (x.clamp(0.0, 1.0) * iw).floor().min(iw - 1.0).max(0.0);
+        let py = (y.clamp(0.0, 1.0) * ih).floor().min(ih - 1.0).max(0.0);
+        let pw = (w.max(0.0) * iw).ceil().clamp(1.0, iw - px);
+        let ph = (h.max(0.0) * ih).ceil().clamp(1.0, ih - py);
+        img.crop_imm(px as u32, py as u32, pw as u32, ph as u32)
+    }
+}
+
+/// Result of an asynchronous analysis, computed off the Qt thread.
+enum AnalysisResult {
+    Color([u8; 3]),
+    Luminance(f64),
+    Palette(Palette),
+    /// Relative luminance and L*.
+    Perceptual(f64, f64),
+    /// The image could not be decoded or analysed.
+    Failed(String),
+}
+
+impl Analysis {
+    /// Run on every sampled frame of an image; statistics of animations
+    /// cover all frames rather than just the first.
+    fn run(self, frames: Result<Vec<DynamicImage>, LoadError>) -> AnalysisResult {
+        let frames = match frames {
+            Ok(frames) => frames,
+            Err(e) => return AnalysisResult::Failed(e.to_string()),
+        };
+        match self {
+            Analysis::DominantColor => match dominant_color(&montage(&frames, 128), 128) {
+                Some(rgb) => AnalysisResult::Color(rgb),
+                None => AnalysisResult::Failed("no opaque pixels".to_string()),
+            },
+            Analysis::Luminance => AnalysisResult::Luminance(mean(
+                frames.iter().map(|img| average_luminance(img, 128)),
+            )),
+            Analysis::Palette(count) => AnalysisResult::Palette(palette::extract(
+                &montage(&frames, palette::SAMPLE_SIZE),
+                count,
+            )),
+            Analysis::Perceptual(region) => {
+                let luminance = mean(frames.iter().map(|img| match region {
+                    Some(region) => relative_luminance(&region.crop(img)),
+                    None => relative_luminance(img),
+                }));
+                AnalysisResult::Perceptual(luminance, lab_lightness(luminance))
+            }
+        }
+    }
+}
+
+impl AnalysisResult {
+    /// Call `callback` with the result, or with `null` and the error
+    /// message on failure.
+    fn deliver(self, callback: &QJSValue) {
+        if !callback.is_callable() {
+            return;
+        }
+        let arg = match self {
+            AnalysisResult::Color(rgb) => QJSValue::from(&rgb_to_color(rgb)),
+            AnalysisResult::Luminance(l) => QJSValue::from(l),
+            AnalysisResult::Palette(p) => QJSValue::from(&QVariant::from(&palette_to_map(&p))),
+            AnalysisResult::Perceptual(luminance, lightness) => {
+                let mut map = QVariantMap::default();
+                map.insert("luminance", QVariant::from(&luminance));
+                map.insert("lightness", QVariant::from(&lightness));
+                QJSValue::from(&QVariant::from(&map))
+            }
+            AnalysisResult::Failed(error) => {
+                callback.call(&[QJSValue::null(), QJSValue::from(&QString::from(error))]);
+                return;
+            }
+        };
+        callback.call(&[arg]);
+    }
+}
+
+/// `toLocalFile` for internal use, keeping paths that are not valid UTF-8
+/// intact. Empty for non-local URLs.
+fn local_path(url: &QUrl) -> PathBuf {
+    url::local_path(&url.to_string()).unwrap_or_default()
+}
+
+/// Decode `path` for analysis, sampling animations.
+fn load(path: &Path) -> Result<Vec<DynamicImage>, LoadError> {
+    image_loader::load_frames(path, ANALYSIS_FRAMES)
+        .inspect_err(|e| eprintln!("CUtils: unable to open {}: {e}", path.display()))
+}
+
+/// Lay `frames` side by side as equal strips of a `size` square, so colour
+/// statistics weigh every frame equally.
+fn montage(frames: &[DynamicImage], size: u32) -> DynamicImage {
+    if let [img] = frames {
+        return img.clone();
+    }
+    let strip = (size / frames.len().max(1) as u32).max(1);
+    let mut out = RgbaImage::new(strip * frames.len() as u32, size);
+    for (i, frame) in frames.iter().enumerate() {
+        let scaled = frame.resize_exact(strip, size, FilterType::Triangle).to_rgba8();
+        image::imageops::replace(&mut out, &scaled, i as i64 * strip as i64, 0);
+    }
+    DynamicImage::ImageRgba8(out)
+}
+
+/// Arithmetic mean, or NaN for no values.
+fn mean(values: impl Iterator<Item = f64>) -> f64 {
+    let (sum, count) = values.fold((0.0, 0usize), |(s, c), v| (s + v, c + 1));
+    if count == 0 {
+        f64::NAN
+    } else {
+        sum / count as f64
+    }
+}
+
+fn rgb_to_color([r, g, b]: [u8; 3]) -> QColor {
+    QColor::from_rgb(r as i32, g as i32, b as i32)
+}
+
+/// Shape a `Palette` as documented on `getPalette`.
+fn palette_to_map(palette: &Palette) -> QVariantMap {
+    let total = palette.total_population().max(1) as f64;
+    let mut swatches = QVariantList::default();
+    for swatch in &palette.swatches {
+        let mut entry = QVariantMap::default();
+        entry.insert("color", QVariant::from(&rgb_to_color(swatch.rgb)));
+        entry.insert("population", QVariant::from(&(swatch.population as i32)));
+        entry.insert("weight", QVariant::from(&(swatch.population as f64 / total)));
+        swatches.append(QVariant::from(&entry));
+    }
+
+    let mut map = QVariantMap::default();
+    if let Some(dominant) = palette.swatches.first() {
+        map.insert("dominant", QVariant::from(&rgb_to_color(dominant.rgb)));
+    }
+    map.insert("swatches", QVariant::from(&swatches));
+    for (role, swatch) in &palette.roles {
+        if let Some(swatch) = swatch {
+            map.insert(role.name(), QVariant::from(&rgb_to_color(swatch.rgb)));
+        }
+    }
+    map
+}
+
+/// Most common color of `img` after downscaling to at most `size` pixels.
+fn dominant_color(img: &DynamicImage, size: u32) -> Option<[u8; 3]> {
+    // Downscaling for performance.
+    // Triangle here is a decent middle of the road quality/speed tradeoff.
+    let resized = img.resize(size, size, FilterType::Triangle);
+
+    // Converting RGB8 (drop alpha)
+    // ColorThief works with raw RGB/RGBA buffers.
+    let rgb = resized.to_rgb8();
+
+    // Sampling every Nth pixel,
+    // with 10 being a good speed/quality default.
+    let quality = 10usize;
+
+    get_color(rgb.as_raw(), ColorFormat::Rgb, quality)
+        .ok()
+        .map(|c| [c.r, c.g, c.b])
+}
+
+/// Mean luminance of `img` after downscaling to at most `size` pixels.
+fn average_luminance(img: &DynamicImage, size: u32) -> f64 {
+    let resized = img.resize(size, size, FilterType::Triangle);
+    let mut total_luma = 0f64;
+    let mut count = 0u64;
+    for pixel in resized.pixels() {
+        let channels = pixel.2.to_rgb();
+        let r = channels[0] as f64 / 255.0;
+        let g = channels[1] as f64 / 255.0;
+        let b = channels[2] as f64 / 255.0;
+        // Luminance formula
+        total_luma += 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        count += 1;
+    }
+    if count > 0 {
+        total_luma / (count as f64)
+    } else {
+        0.0
+    }
+}
+
+/// Decode an sRGB channel to linear light.
+fn srgb_to_linear(c: u8) -> f64 {
+    let c = c as f64 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Mean relative luminance (Y) of `img`. Every pixel is linearised before
+/// averaging, since a mean of gamma encoded values (or a downscale, which is
+/// one) is darker than the light the pixels actually emit.
+fn relative_luminance(img: &DynamicImage) -> f64 {
+    let rgb = img.to_rgb8();
+    let count = rgb.pixels().len();
+    if count == 0 {
+        return 0.0;
+    }
+    let linear: [f64; 256] = std::array::from_fn(|c| srgb_to_linear(c as u8));
+    let total: f64 = rgb
+        .pixels()
+        .map(|p| {
+            0.2126 * linear[p[0] as usize]
+                + 0.7152 * linear[p[1] as usize]
+                + 0.0722 * linear[p[2] as usize]
+        })
+        .sum();
+    total / count as f64
+}
+
+/// CIELAB L* (0 to 100) for a relative luminance.
+fn lab_lightness(y: f64) -> f64 {
+    const EPSILON: f64 = 216.0 / 24389.0;
+    const KAPPA: f64 = 24389.0 / 27.0;
+    if y > EPSILON {
+        116.0 * y.cbrt() - 16.0
+    } else {
+        KAPPA * y
+    }
+}
+
+/// Grab the current contents of `item` and pass them, with the device pixel
+/// ratio, to `ready` once the grab completes, or the reason it failed.
+fn grab_item(
+    item: &mut QQuickItem,
+    ready: impl FnOnce(Result<(RgbaImage, f64), String>) + Send + 'static,
+) {
+    let Some(grab) = item.grab_to_image() else {
+        ready(Err("unable to grab item".to_string()));
+        return;
+    };
+    grab.on_ready(move |result| {
+        let image = result
+            .image()
+            .convert_to_format(QImageFormat::Format_RGBA8888);
+        let dpr = image.device_pixel_ratio();
+        let (width, height) = (image.width() as u32, image.height() as u32);
+        ready(
+            RgbaImage::from_raw(width, height, image.bits().to_vec())
+                .map(|img| (img, dpr))
+                .ok_or_else(|| "grab returned an invalid image".to_string()),
+        );
+    });
+}
+
+/// Report the outcome of `saveItem`: the written path, or `null` and the
+/// error message.
+fn deliver_saved(callback: &QJSValue, saved: Result<PathBuf, String>) {
+    match saved {
+        Ok(path) if callback.is_callable() => {
+            callback.call(&[QJSValue::from(&QString::from(path.to_string_lossy().as_ref()))]);
+        }
+        Ok(_) => {}
+        Err(error) => {
+            eprintln!("CUtils::saveItem: {error}");
+            if callback.is_callable() {
+                callback.call(&[QJSValue::null(), QJSValue::from(&QString::from(error))]);
             }
-            Err(_) => 0.0,
         }
     }
 }
 
+/// Crop a grabbed image to `rect`, given in logical pixels. Fails if the
+/// rect does not cover at least one pixel of the image.
+fn crop_to_rect(img: RgbaImage, rect: &QRectF, dpr: f64) -> Result<RgbaImage, String> {
+    if rect.is_empty() {
+        return Ok(img);
+    }
+    let x = ((rect.x() * dpr).round().max(0.0) as u32).min(img.width());
+    let y = ((rect.y() * dpr).round().max(0.0) as u32).min(img.height());
+    let w = ((rect.width() * dpr).round().max(0.0) as u32).min(img.width() - x);
+    let h = ((rect.height() * dpr).round().max(0.0) as u32).min(img.height() - y);
+    if w == 0 || h == 0 {
+        return Err(format!(
+            "crop rect {}x{}+{}+{} lies outside the {}x{} item",
+            rect.width(),
+            rect.height(),
+            rect.x(),
+            rect.y(),
+            img.width(),
+            img.height()
+        ));
+    }
+    Ok(image::imageops::crop_imm(&img, x, y, w, h).to_image())
+}
+
+/// Encode `img` in the format implied by the extension of `path`.
+fn save_image(img: &RgbaImage, path: &Path) -> Result<(), String> {
+    let format = match ImageFormat::from_path(path) {
+        Ok(f @ (ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP | ImageFormat::Qoi)) => f,
+        _ => ImageFormat::Png,
+    };
+    let mut encoded = Cursor::new(Vec::new());
+    let written = match format {
+        // No alpha channel in JPEG.
+        ImageFormat::Jpeg => image::DynamicImage::ImageRgba8(img.clone())
+            .to_rgb8()
+            .write_to(&mut encoded, format),
+        _ => img.write_to(&mut encoded, format),
+    };
+    written.map_err(|e| e.to_string())?;
+    // Encode first and swap the file in whole, so watchers never pick up a
+    // half-written screenshot.
+    file_ops::write_atomic(path, encoded.get_ref()).map_err(|e| e.message)
+}
+
 /// Register CUtils with the QML engine
 pub fn registe() {
     cxx_qt::qml_register_type::<CUtils>("Vela", 1, 0, "CUtils");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn srgb_to_linear_follows_the_transfer_function() {
+        assert_eq!(srgb_to_linear(0), 0.0);
+        assert!(close(srgb_to_linear(255), 1.0));
+        // Below the 0.04045 knee the curve is linear.
+        assert!(close(srgb_to_linear(10), 10.0 / 255.0 / 12.92));
+        assert!(close(srgb_to_linear(128), 0.215_860_5));
+        assert!((1..=255).all(|c| srgb_to_linear(c) > srgb_to_linear(c - 1)));
+    }
+
+    #[test]
+    fn lab_lightness_covers_both_segments() {
+        assert_eq!(lab_lightness(0.0), 0.0);
+        assert!(close(lab_lightness(1.0), 100.0));
+        assert!(close(lab_lightness(0.18), 49.496_107));
+        // The linear and cube root segments meet at EPSILON.
+        let epsilon = 216.0 / 24389.0;
+        assert!((lab_lightness(epsilon) - lab_lightness(epsilon + 1e-12)).abs() < 1e-6);
+        assert!(close(lab_lightness(0.001), 0.903_296));
+    }
+
+    #[test]
+    fn relative_luminance_linearises_before_averaging() {
+        // Half black, half white emits half the light of white, which is
+        // mid grey 188 rather than the gamma encoded average 128.
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 64, |x, _| {
+            if x < 32 {
+                Rgb([0, 0, 0])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        }));
+        assert!(close(relative_luminance(&img), 0.5));
+        let grey = DynamicImage::ImageRgb8(RgbImage::from_pixel(3, 3, Rgb([128, 128, 128])));
+        assert!(close(relative_luminance(&grey), srgb_to_linear(128)));
+    }
+
+    #[test]
+    fn region_crop_maps_normalized_coordinates() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(100, 50, |x, y| {
+            Rgb([x as u8, y as u8, 0])
+        }));
+        let cropped = Region([0.25, 0.5, 0.5, 0.5]).crop(&img);
+        assert_eq!(cropped.dimensions(), (50, 25));
+        assert_eq!(cropped.to_rgb8().get_pixel(0, 0), &Rgb([25, 25, 0]));
+    }
+
+    #[test]
+    fn region_crop_clamps_to_the_image() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(10, 10));
+        assert_eq!(Region([0.5, 0.5, 2.0, 2.0]).crop(&img).dimensions(), (5, 5));
+        assert_eq!(
+            Region([-1.0, -1.0, 0.3, 0.3]).crop(&img).dimensions(),
+            (3, 3)
+        );
+        // Empty or out of range regions still keep a pixel.
+        assert_eq!(Region([0.2, 0.2, 0.0, 0.0]).crop(&img).dimensions(), (1, 1));
+        assert_eq!(Region([1.0, 1.0, 0.5, 0.5]).crop(&img).dimensions(), (1, 1));
+    }
+}
@@ -0,0 +1,289 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use serde_json::Value;
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+use crate::file_ops;
+
+/// Caches fetched longer ago than this are refreshed when a fetcher is available.
+pub const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The ECB reference rates, published every working day.
+pub const DEFAULT_URL: &str = "https://www.ecb.europa.eu/stats/eurofxref/eurofxref-daily.xml";
+
+/// Exchange rates at one point in time: how many units of each currency one
+/// unit of `base` buys.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Snapshot {
+    pub base: String,
+    /// Seconds since the Unix epoch.
+    pub timestamp: i64,
+    pub rates: Vec<(String, f64)>,
+}
+
+impl Snapshot {
+    /// Parse a cache file. Accepts ECB `eurofxref` XML and JSON shaped like
+    /// `{ "base": "EUR", "timestamp": 1700000000, "rates": { "USD": 1.07 } }`,
+    /// which also covers the common free APIs (`base_code`, `date` and
+    /// `time_last_update_unix` are understood too).
+    pub fn parse(data: &str) -> Result<Self, String> {
+        let snapshot = if data.trim_start().starts_with('<') {
+            parse_ecb_xml(data)?
+        } else {
+            parse_json(data)?
+        };
+        if snapshot.rates.is_empty() {
+            return Err("no rates found".to_string());
+        }
+        Ok(snapshot)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&data)
+    }
+
+    /// Rates relative to `base`, which must be the snapshot's base or one of
+    /// its currencies.
+    pub fn rebased(&self, base: &str) -> Option<Vec<(String, f64)>> {
+        if self.base.eq_ignore_ascii_case(base) {
+            return Some(self.rates.clone());
+        }
+        let (_, per_base) = self
+            .rates
+            .iter()
+            .find(|(code, rate)| code.eq_ignore_ascii_case(base) && *rate > 0.0)?;
+        let mut rates: Vec<(String, f64)> = self
+            .rates
+            .iter()
+            .filter(|(code, _)| !code.eq_ignore_ascii_case(base))
+            .map(|(code, rate)| (code.clone(), rate / per_base))
+            .collect();
+        rates.push((self.base.clone(), 1.0 / per_base));
+        Some(rates)
+    }
+}
+
+/// Whether the cache at `path` is missing or was fetched more than `MAX_AGE`
+/// ago. Its modification time is when `refresh` wrote it; the snapshot's own
+/// timestamp is the publication date, which lags by days over weekends and
+/// holidays when the ECB publishes nothing new.
+pub(crate) fn is_stale(path: &Path) -> bool {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map_or(true, |fetched| expired(fetched, SystemTime::now()))
+}
+
+fn expired(fetched: SystemTime, now: SystemTime) -> bool {
+    now.duration_since(fetched).is_ok_and(|age| age > MAX_AGE)
+}
+
+/// Source of fresh rate data, returned in any format `Snapshot::parse`
+/// accepts. Runs on a worker thread.
+pub(crate) trait RateFetcher: Send + Sync {
+    fn fetch(&self) -> Result<String, String>;
+}
+
+/// Downloads `url` with curl, which every desktop already has, rather than
+/// linking an HTTP stack for one request a day.
+pub(crate) struct CurlFetcher {
+    pub url: String,
+}
+
+impl RateFetcher for CurlFetcher {
+    fn fetch(&self) -> Result<String, String> {
+        let output = Command::new("curl")
+            .args(["-fsSL", "--max-time", "15", &self.url])
+            .output()
+            .map_err(|e| format!("unable to run curl: {e}"))?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+        }
+        String::from_utf8(output.stdout).map_err(|e| e.to_string())
+    }
+}
+
+/// Fetch, validate and atomically replace the cache at `path`. The old cache
+/// is kept if anything fails, so evaluation keeps working offline.
+pub(crate) fn refresh(fetcher: &dyn RateFetcher, path: &Path) -> Result<Snapshot, String> {
+    let data = fetcher.fetch()?;
+    let snapshot = Snapshot::parse(&data)?;
+    if let Some(parent) = path.parent() {
+        file_ops::make_dirs(parent).map_err(|e| e.message)?;
+    }
+    file_ops::write_atomic(path, data.as_bytes()).map_err(|e| e.message)?;
+    Ok(snapshot)
+}
+
+fn parse_json(data: &str) -> Result<Snapshot, String> {
+    let json: Value = serde_json::from_str(data).map_err(|e| e.to_string())?;
+    let base = json
+        .get("base")
+        .or_else(|| json.get("base_code"))
+        .and_then(Value::as_str)
+        .ok_or("missing base currency")?
+        .to_ascii_uppercase();
+    let timestamp = json
+        .get("timestamp")
+        .or_else(|| json.get("time_last_update_unix"))
+        .and_then(Value::as_i64)
+        .or_else(|| json.get("date").and_then(Value::as_str).and_then(date_timestamp))
+        .ok_or("missing timestamp")?;
+    let rates = json
+        .get("rates")
+        .and_then(Value::as_object)
+        .ok_or("missing rates")?
+        .iter()
+        .filter_map(|(code, rate)| Some((code.to_ascii_uppercase(), rate.as_f64()?)))
+        .filter(|(code, rate)| *rate > 0.0 && *code != base)
+        .collect();
+    Ok(Snapshot {
+        base,
+        timestamp,
+        rates,
+    })
+}
+
+/// The ECB feed nests `<Cube time="...">` and `<Cube currency rate/>`
+/// elements; scanning attributes is enough for it.
+fn parse_ecb_xml(data: &str) -> Result<Snapshot, String> {
+    let mut timestamp = None;
+    let mut rates = Vec::new();
+    for element in data.split('<').filter(|e| e.starts_with("Cube")) {
+        if let Some(time) = attribute(element, "time") {
+            timestamp = date_timestamp(time);
+        }
+        let code = attribute(element, "currency");
+        let rate = attribute(element, "rate").and_then(|r| r.parse::<f64>().ok());
+        if let (Some(code), Some(rate)) = (code, rate) {
+            rates.push((code.to_ascii_uppercase(), rate));
+        }
+    }
+    Ok(Snapshot {
+        base: "EUR".to_string(),
+        timestamp: timestamp.ok_or("missing time")?,
+        rates,
+    })
+}
+
+fn attribute<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+    for quote in ['\'', '"'] {
+        let prefix = format!("{name}={quote}");
+        if let Some(start) = element.find(&prefix) {
+            let rest = &element[start + prefix.len()..];
+            return rest.find(quote).map(|end| &rest[..end]);
+        }
+    }
+    None
+}
+
+/// Midnight UTC of a `YYYY-MM-DD` date.
+fn date_timestamp(date: &str) -> Option<i64> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    Some(NaiveDateTime::from(date).and_utc().timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    const ECB: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gesmes:Envelope xmlns:gesmes="http://www.gesmes.org/xml/2002-08-01">
+	<gesmes:subject>Reference rates</gesmes:subject>
+	<Cube>
+		<Cube time='2024-03-15'>
+			<Cube currency='USD' rate='1.0887'/>
+			<Cube currency='JPY' rate='162.30'/>
+			<Cube currency="GBP" rate="0.85520"/>
+		</Cube>
+	</Cube>
+</gesmes:Envelope>"#;
+
+    fn rate(rates: &[(String, f64)], code: &str) -> f64 {
+        rates.iter().find(|(c, _)| c == code).unwrap().1
+    }
+
+    #[test]
+    fn parses_ecb_xml() {
+        let snapshot = Snapshot::parse(ECB).unwrap();
+        assert_eq!(snapshot.base, "EUR");
+        assert_eq!(snapshot.timestamp, 1_710_460_800);
+        assert_eq!(
+            snapshot.rates,
+            vec![
+                ("USD".to_string(), 1.0887),
+                ("JPY".to_string(), 162.30),
+                ("GBP".to_string(), 0.8552),
+            ]
+        );
+        assert!(parse_ecb_xml("<Cube><Cube currency='USD' rate='1'/></Cube>").is_err());
+        assert!(Snapshot::parse("<Cube time='2024-03-15'></Cube>").is_err());
+    }
+
+    #[test]
+    fn parses_json() {
+        let data = r#"{ "base": "usd", "timestamp": 1700000000,
+            "rates": { "eur": 0.9, "USD": 1, "XXX": 0, "bad": "x" } }"#;
+        let snapshot = Snapshot::parse(data).unwrap();
+        assert_eq!(snapshot.base, "USD");
+        assert_eq!(snapshot.timestamp, 1_700_000_000);
+        assert_eq!(snapshot.rates, vec![("EUR".to_string(), 0.9)]);
+
+        let data = r#"{ "base_code": "EUR", "time_last_update_unix": 1700000000,
+            "rates": { "USD": 1.1 } }"#;
+        let snapshot = Snapshot::parse(data).unwrap();
+        assert_eq!(snapshot.base, "EUR");
+        assert_eq!(snapshot.timestamp, 1_700_000_000);
+
+        let data = r#"{ "base": "EUR", "date": "2024-03-15", "rates": { "USD": 1.1 } }"#;
+        assert_eq!(Snapshot::parse(data).unwrap().timestamp, 1_710_460_800);
+
+        assert!(Snapshot::parse(r#"{ "timestamp": 1, "rates": { "USD": 1.1 } }"#).is_err());
+        assert!(Snapshot::parse(r#"{ "base": "EUR", "rates": { "USD": 1.1 } }"#).is_err());
+        assert!(Snapshot::parse(r#"{ "base": "EUR", "timestamp": 1, "rates": {} }"#).is_err());
+        assert!(Snapshot::parse("not json").is_err());
+    }
+
+    #[test]
+    fn rebases_on_a_listed_currency() {
+        let snapshot = Snapshot::parse(ECB).unwrap();
+        assert_eq!(snapshot.rebased("eur"), Some(snapshot.rates.clone()));
+
+        let rates = snapshot.rebased("USD").unwrap();
+        assert_eq!(rates.len(), 3);
+        assert!(rates.iter().all(|(code, _)| code != "USD"));
+        assert!((rate(&rates, "EUR") - 1.0 / 1.0887).abs() < 1e-12);
+        assert!((rate(&rates, "JPY") - 162.30 / 1.0887).abs() < 1e-9);
+
+        assert_eq!(snapshot.rebased("CHF"), None);
+    }
+
+    #[test]
+    fn staleness_follows_the_fetch_time() {
+        let now = SystemTime::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+        assert!(!expired(now, now));
+        assert!(!expired(now - day + Duration::from_secs(60), now));
+        assert!(expired(now - day - Duration::from_secs(60), now));
+        // A clock that went backwards does not force a refresh.
+        assert!(!expired(now + day, now));
+
+        let path = std::env::temp_dir().join(format!("vela-rates-{}", std::process::id()));
+        assert!(is_stale(&path));
+        // Rates published days ago but fetched just now, as over a weekend.
+        fs::write(&path, ECB).unwrap();
+        assert!(!is_stale(&path));
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(now - 2 * day)
+            .unwrap();
+        assert!(is_stale(&path));
+        fs::remove_file(&path).unwrap();
+    }
+}
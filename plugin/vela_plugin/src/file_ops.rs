@@ -0,0 +1,510 @@
+use qt6_core::{QString, QVariant, QVariantMap};
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Broad reason a file operation failed, as reported to QML.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum FileErrorKind {
+    NotFound,
+    Permission,
+    CrossDevice,
+    Exists,
+    Other,
+}
+
+impl FileErrorKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            FileErrorKind::NotFound => "notFound",
+            FileErrorKind::Permission => "permission",
+            FileErrorKind::CrossDevice => "crossDevice",
+            FileErrorKind::Exists => "exists",
+            FileErrorKind::Other => "other",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct FileError {
+    pub kind: FileErrorKind,
+    pub message: String,
+}
+
+impl FileError {
+    pub fn new(kind: FileErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    /// Classify `e`, prefixing its message with what was being done to `path`.
+    pub fn io(e: io::Error, action: &str, path: &Path) -> Self {
+        let kind = match e.kind() {
+            ErrorKind::NotFound => FileErrorKind::NotFound,
+            ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem => {
+                FileErrorKind::Permission
+            }
+            ErrorKind::CrossesDevices => FileErrorKind::CrossDevice,
+            ErrorKind::AlreadyExists => FileErrorKind::Exists,
+            _ => FileErrorKind::Other,
+        };
+        Self::new(kind, format!("unable to {action} {}: {e}", path.display()))
+    }
+}
+
+pub(crate) type FileResult<T = ()> = Result<T, FileError>;
+
+/// Shape a result for QML: `{ ok, error, message }`, where `error` is one of
+/// the `FileErrorKind` names (empty on success) and `message` is readable.
+pub(crate) fn result_to_map<T>(result: &FileResult<T>) -> QVariantMap {
+    let (kind, message) = match result {
+        Ok(_) => ("", ""),
+        Err(e) => (e.kind.name(), e.message.as_str()),
+    };
+    let mut map = QVariantMap::default();
+    map.insert("ok", QVariant::from(&result.is_ok()));
+    map.insert("error", QVariant::from(&QString::from(kind)));
+    map.insert("message", QVariant::from(&QString::from(message)));
+    map
+}
+
+/// Copy `source` to `target`. Without `overwrite` an existing target is
+/// never touched: the target is created exclusively, so a file appearing
+/// between check and copy is still reported as `Exists`. With `overwrite`
+/// the copy goes to a temporary sibling first and is renamed into place,
+/// so a failed copy leaves the old target intact.
+pub(crate) fn copy_file(source: &Path, target: &Path, overwrite: bool) -> FileResult {
+    let mut src = File::open(source).map_err(|e| FileError::io(e, "open", source))?;
+    let meta = src.metadata().map_err(|e| FileError::io(e, "stat", source))?;
+    if meta.is_dir() {
+        return Err(FileError::new(
+            FileErrorKind::Other,
+            format!("{} is a directory", source.display()),
+        ));
+    }
+
+    let staging = if overwrite { temp_sibling(target) } else { target.to_path_buf() };
+    let mut dst = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&staging)
+        .map_err(|e| FileError::io(e, "create", target))?;
+    let copied = io::copy(&mut src, &mut dst)
+        .and_then(|_| dst.set_permissions(meta.permissions()))
+        .and_then(|_| if overwrite { dst.sync_all() } else { Ok(()) })
+        .map_err(|e| FileError::io(e, "write", target));
+    drop(dst);
+    if let Err(e) = copied {
+        let _ = fs::remove_file(&staging);
+        return Err(e);
+    }
+    if overwrite {
+        fs::rename(&staging, target).map_err(|e| {
+            let _ = fs::remove_file(&staging);
+            FileError::io(e, "replace", target)
+        })?;
+        sync_parent(target);
+    }
+    Ok(())
+}
+
+/// Remove the file at `path`. Directories are refused.
+pub(crate) fn delete_file(path: &Path) -> FileResult {
+    let meta = fs::symlink_metadata(path).map_err(|e| FileError::io(e, "stat", path))?;
+    if meta.is_dir() {
+        return Err(FileError::new(
+            FileErrorKind::Other,
+            format!("{} is a directory", path.display()),
+        ));
+    }
+    fs::remove_file(path).map_err(|e| FileError::io(e, "delete", path))
+}
+
+/// Hidden, unique path next to `path`, on the same filesystem so it can be
+/// renamed over it. The counter keeps concurrent writers in this process
+/// from sharing one.
+pub(crate) fn temp_sibling(path: &Path) -> PathBuf {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{name}.{}.{n}.tmp", std::process::id()))
+}
+
+/// Replace `path` with `data` so readers only ever see the old or the new
+/// contents: write a temporary sibling, fsync it, rename it into place and
+/// fsync the directory. An existing file keeps its permissions.
+pub(crate) fn write_atomic(path: &Path, data: &[u8]) -> FileResult {
+    let temp = temp_sibling(path);
+    let written = (|| {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(&temp)?;
+        if let Ok(meta) = fs::metadata(path) {
+            file.set_permissions(meta.permissions())?;
+        }
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    })();
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp);
+        return Err(FileError::io(e, "write", path));
+    }
+    sync_parent(path);
+    Ok(())
+}
+
+/// Move `source` to `target`, falling back to copy and delete across
+/// filesystems. Without `overwrite` an existing target is reported as
+/// `Exists`; for files the check is atomic via a hard link.
+pub(crate) fn move_path(source: &Path, target: &Path, overwrite: bool) -> FileResult {
+    let meta = fs::symlink_metadata(source).map_err(|e| FileError::io(e, "stat", source))?;
+    if !overwrite {
+        if meta.is_file() {
+            match fs::hard_link(source, target) {
+                Ok(()) => {
+                    return fs::remove_file(source)
+                        .map_err(|e| FileError::io(e, "remove", source));
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    return Err(FileError::io(e, "move to", target));
+                }
+                // Cross-device or no hard link support: fall through.
+                Err(_) => {}
+            }
+        }
+        if fs::symlink_metadata(target).is_ok() {
+            return Err(FileError::new(
+                FileErrorKind::Exists,
+                format!("{} already exists", target.display()),
+            ));
+        }
+    }
+    match fs::rename(source, target) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            if meta.is_dir() {
+                copy_dir(source, target, overwrite, |_, _, _| {})?;
+                fs::remove_dir_all(source).map_err(|e| FileError::io(e, "remove", source))
+            } else {
+                copy_file(source, target, overwrite)?;
+                fs::remove_file(source).map_err(|e| FileError::io(e, "remove", source))
+            }
+        }
+        Err(e) => Err(FileError::io(e, "move", source)),
+    }
+}
+
+/// Create `path` and any missing parents.
+pub(crate) fn make_dirs(path: &Path) -> FileResult {
+    fs::create_dir_all(path).map_err(|e| FileError::io(e, "create", path))
+}
+
+/// Recursively copy the directory `source` to `target`, calling
+/// `progress(copied_bytes, total_bytes, current_file)` after each file.
+/// Symlinks are recreated rather than followed. Existing directories are
+/// merged into; without `overwrite` any other existing entry is reported as
+/// `Exists` before anything is copied.
+pub(crate) fn copy_dir(
+    source: &Path,
+    target: &Path,
+    overwrite: bool,
+    mut progress: impl FnMut(u64, u64, &Path),
+) -> FileResult {
+    let meta = fs::metadata(source).map_err(|e| FileError::io(e, "stat", source))?;
+    if !meta.is_dir() {
+        return Err(FileError::new(
+            FileErrorKind::Other,
+            format!("{} is not a directory", source.display()),
+        ));
+    }
+    let source_abs = fs::canonicalize(source).map_err(|e| FileError::io(e, "resolve", source))?;
+    let target_abs = resolve(target).map_err(|e| FileError::io(e, "resolve", target))?;
+    if target_abs.starts_with(&source_abs) {
+        return Err(FileError::new(
+            FileErrorKind::Other,
+            format!("cannot copy {} into itself", source.display()),
+        ));
+    }
+
+    let entries = walk(source)?;
+    let dest_of = |path: &Path| target.join(path.strip_prefix(source).unwrap_or(path));
+    if !overwrite {
+        // Checked up front so a conflict does not leave a partial copy.
+        let conflict = entries
+            .iter()
+            .map(|(path, meta)| (dest_of(path), meta.is_dir()))
+            .find(|(dest, is_dir)| {
+                fs::symlink_metadata(dest).is_ok_and(|existing| !(*is_dir && existing.is_dir()))
+            });
+        if let Some((dest, _)) = conflict {
+            return Err(FileError::new(
+                FileErrorKind::Exists,
+                format!("{} already exists", dest.display()),
+            ));
+        }
+    }
+
+    let total: u64 = entries.iter().map(|(_, m)| if m.is_file() { m.len() } else { 0 }).sum();
+    let mut copied = 0u64;
+    make_dirs(target)?;
+    for (path, meta) in entries {
+        let dest = dest_of(&path);
+        if meta.is_dir() {
+            make_dirs(&dest)?;
+        } else if meta.is_symlink() {
+            let link = fs::read_link(&path).map_err(|e| FileError::io(e, "read", &path))?;
+            if overwrite && fs::symlink_metadata(&dest).is_ok() {
+                delete_file(&dest)?;
+            }
+            std::os::unix::fs::symlink(link, &dest)
+                .map_err(|e| FileError::io(e, "create", &dest))?;
+        } else if meta.is_file() {
+            copy_file(&path, &dest, overwrite)?;
+            copied += meta.len();
+            progress(copied, total, &path);
+        }
+    }
+    Ok(())
+}
+
+/// `path` made absolute with symlinks and `..` resolved as far as it exists,
+/// so it can be compared with canonical paths before it is created.
+fn resolve(path: &Path) -> io::Result<PathBuf> {
+    let mut resolved = PathBuf::new();
+    for component in std::path::absolute(path)?.components() {
+        match component {
+            Component::CurDir => {}
+            // `resolved` has no symlinks left, so this is the real parent.
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(name) => {
+                resolved.push(name);
+                match fs::canonicalize(&resolved) {
+                    Ok(real) => resolved = real,
+                    // Missing parts would be created as plain directories.
+                    Err(e) if e.kind() == ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            other => resolved.push(other),
+        }
+    }
+    Ok(resolved)
+}
+
+/// Every entry under `dir`, parents before children.
+fn walk(dir: &Path) -> FileResult<Vec<(PathBuf, fs::Metadata)>> {
+    let mut out = Vec::new();
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(current) = stack.pop() {
+        let read = fs::read_dir(&current).map_err(|e| FileError::io(e, "read", &current))?;
+        for entry in read {
+            let entry = entry.map_err(|e| FileError::io(e, "read", &current))?;
+            let path = entry.path();
+            let meta = fs::symlink_metadata(&path).map_err(|e| FileError::io(e, "stat", &path))?;
+            if meta.is_dir() {
+                stack.push(path.clone());
+            }
+            out.push((path, meta));
+        }
+    }
+    Ok(out)
+}
+
+/// Flush the directory entry of a rename; failures only cost durability.
+fn sync_parent(path: &Path) {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        let _ = File::open(parent).and_then(|dir| dir.sync_all());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("vela-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        /// Names in the directory, sorted, to spot stray temporary files.
+        fn names(&self) -> Vec<String> {
+            let mut names: Vec<String> = fs::read_dir(&self.0)
+                .unwrap()
+                .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn copy_file_keeps_an_existing_target_without_overwrite() {
+        let tmp = TempDir::new("ops-copy");
+        let (source, target) = (tmp.0.join("source"), tmp.0.join("target"));
+        fs::write(&source, "new").unwrap();
+        fs::write(&target, "old").unwrap();
+
+        let err = copy_file(&source, &target, false).unwrap_err();
+        assert_eq!(err.kind, FileErrorKind::Exists);
+        assert_eq!(read(&target), "old");
+
+        fs::set_permissions(&source, fs::Permissions::from_mode(0o640)).unwrap();
+        copy_file(&source, &target, true).unwrap();
+        assert_eq!(read(&target), "new");
+        assert_eq!(fs::metadata(&target).unwrap().permissions().mode() & 0o777, 0o640);
+        assert_eq!(tmp.names(), ["source", "target"]);
+
+        let missing = copy_file(&tmp.0.join("missing"), &target, true).unwrap_err();
+        assert_eq!(missing.kind, FileErrorKind::NotFound);
+        let dir = copy_file(&tmp.0, &tmp.0.join("copy"), false).unwrap_err();
+        assert_eq!(dir.kind, FileErrorKind::Other);
+    }
+
+    #[test]
+    fn write_atomic_replaces_contents_and_keeps_permissions() {
+        let tmp = TempDir::new("ops-write");
+        let path = tmp.0.join("state");
+        write_atomic(&path, b"first").unwrap();
+        assert_eq!(read(&path), "first");
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        write_atomic(&path, b"second").unwrap();
+        assert_eq!(read(&path), "second");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(tmp.names(), ["state"]);
+
+        let err = write_atomic(&tmp.0.join("missing/state"), b"x").unwrap_err();
+        assert_eq!(err.kind, FileErrorKind::NotFound);
+    }
+
+    #[test]
+    fn temp_siblings_are_unique_and_hidden() {
+        let path = Path::new("/some/dir/file.txt");
+        let (a, b) = (temp_sibling(path), temp_sibling(path));
+        assert_ne!(a, b);
+        assert_eq!(a.parent(), path.parent());
+        assert!(a.file_name().unwrap().to_string_lossy().starts_with(".file.txt."));
+    }
+
+    #[test]
+    fn move_path_refuses_to_clobber() {
+        let tmp = TempDir::new("ops-move");
+        let (source, target) = (tmp.0.join("source"), tmp.0.join("target"));
+        fs::write(&source, "new").unwrap();
+        fs::write(&target, "old").unwrap();
+
+        let err = move_path(&source, &target, false).unwrap_err();
+        assert_eq!(err.kind, FileErrorKind::Exists);
+        assert_eq!((read(&source), read(&target)), ("new".into(), "old".into()));
+
+        let (dir, other) = (tmp.0.join("dir"), tmp.0.join("other"));
+        fs::create_dir(&dir).unwrap();
+        fs::create_dir(&other).unwrap();
+        fs::write(other.join("kept"), "x").unwrap();
+        let err = move_path(&dir, &other, false).unwrap_err();
+        assert_eq!(err.kind, FileErrorKind::Exists);
+        assert!(dir.is_dir() && other.join("kept").exists());
+
+        move_path(&source, &target, true).unwrap();
+        assert_eq!(read(&target), "new");
+        assert!(!source.exists());
+        move_path(&target, &tmp.0.join("moved"), false).unwrap();
+        assert_eq!(read(&tmp.0.join("moved")), "new");
+        assert!(!target.exists());
+    }
+
+    #[test]
+    fn copy_dir_refuses_to_copy_into_itself() {
+        let tmp = TempDir::new("ops-itself");
+        let source = tmp.0.join("source");
+        fs::create_dir_all(source.join("sub")).unwrap();
+        fs::write(source.join("sub/file"), "x").unwrap();
+        symlink(&source, tmp.0.join("alias")).unwrap();
+
+        for target in [
+            source.join("copy"),
+            source.join("sub/deeper/copy"),
+            tmp.0.join("alias/copy"),
+            tmp.0.join("missing/../source/copy"),
+        ] {
+            let err = copy_dir(&source, &target, false, |_, _, _| {}).unwrap_err();
+            assert_eq!(err.kind, FileErrorKind::Other, "{}", target.display());
+            assert!(!target.exists());
+        }
+
+        // A sibling that merely shares the prefix is fine.
+        copy_dir(&source, &tmp.0.join("source-copy"), false, |_, _, _| {}).unwrap();
+        assert_eq!(read(&tmp.0.join("source-copy/sub/file")), "x");
+    }
+
+    #[test]
+    fn copy_dir_checks_conflicts_before_copying() {
+        let tmp = TempDir::new("ops-conflict");
+        let (source, target) = (tmp.0.join("source"), tmp.0.join("target"));
+        fs::create_dir_all(source.join("sub")).unwrap();
+        fs::write(source.join("a"), "a").unwrap();
+        fs::write(source.join("sub/b"), "b").unwrap();
+        fs::create_dir_all(target.join("sub")).unwrap();
+        fs::write(target.join("sub/b"), "old").unwrap();
+
+        let err = copy_dir(&source, &target, false, |_, _, _| {}).unwrap_err();
+        assert_eq!(err.kind, FileErrorKind::Exists);
+        assert!(!target.join("a").exists());
+        assert_eq!(read(&target.join("sub/b")), "old");
+
+        let mut progress = Vec::new();
+        copy_dir(&source, &target, true, |copied, total, _| progress.push((copied, total)))
+            .unwrap();
+        assert_eq!(read(&target.join("sub/b")), "b");
+        assert_eq!(progress.last(), Some(&(2, 2)));
+    }
+
+    #[test]
+    fn io_errors_map_to_kinds() {
+        let kind = |kind: ErrorKind| FileError::io(kind.into(), "open", Path::new("/x")).kind;
+        assert_eq!(kind(ErrorKind::NotFound), FileErrorKind::NotFound);
+        assert_eq!(kind(ErrorKind::PermissionDenied), FileErrorKind::Permission);
+        assert_eq!(kind(ErrorKind::ReadOnlyFilesystem), FileErrorKind::Permission);
+        assert_eq!(kind(ErrorKind::CrossesDevices), FileErrorKind::CrossDevice);
+        assert_eq!(kind(ErrorKind::AlreadyExists), FileErrorKind::Exists);
+        assert_eq!(kind(ErrorKind::Interrupted), FileErrorKind::Other);
+
+        let err = FileError::io(ErrorKind::NotFound.into(), "open", Path::new("/x"));
+        assert!(err.message.starts_with("unable to open /x: "));
+    }
+
+    #[test]
+    fn error_kinds_have_qml_names() {
+        // `result_to_map` reports these as `error`.
+        let names = [
+            FileErrorKind::NotFound,
+            FileErrorKind::Permission,
+            FileErrorKind::CrossDevice,
+            FileErrorKind::Exists,
+            FileErrorKind::Other,
+        ]
+        .map(FileErrorKind::name);
+        assert_eq!(names, ["notFound", "permission", "crossDevice", "exists", "other"]);
+    }
+}
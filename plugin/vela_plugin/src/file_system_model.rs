@@ -0,0 +1,918 @@
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError};
+use cxx_qt::{CxxQtThread, QEnum, QObject, Threading};
+use glob::{MatchOptions, Pattern};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use qt6_core::{QByteArray, QHash, QModelIndex, QString, QStringList, QVariant};
+
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Quiet period after the last filesystem event before rescanning.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(150);
+
+/// Upper bound on how long a continuous burst of events can delay a rescan.
+const WATCH_MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// Extensions treated as images by the `Images` filter.
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "avif", "bmp", "gif", "jpeg", "jpg", "jxl", "png", "svg", "tif", "tiff", "webp",
+];
+
+#[derive(QObject, Default, Clone, PartialEq, Debug)]
+pub struct FileSystemEntry {
+    #[qproperty]
+    pub path: QString,
+
+    #[qproperty(cpp_name = "relativePath")]
+    pub relative_path: QString,
+
+    #[qproperty]
+    pub name: QString,
+
+    #[qproperty(cpp_name = "parentDir")]
+    pub parent_dir: QString,
+
+    #[qproperty]
+    pub suffix: QString,
+
+    #[qproperty]
+    pub size: u64,
+
+    #[qproperty(cpp_name = "isDir")]
+    pub is_dir: bool,
+
+    #[qproperty(cpp_name = "isImage")]
+    pub is_image: bool,
+
+    modified: Option<SystemTime>,
+}
+
+impl FileSystemEntry {
+    fn new(root: &Path, path: &Path, meta: &fs::Metadata) -> Self {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let suffix = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let is_dir = meta.is_dir();
+        Self {
+            path: QString::from(path.to_string_lossy().as_ref()),
+            relative_path: QString::from(relative.to_string_lossy().as_ref()),
+            name: QString::from(
+                path.file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            ),
+            parent_dir: QString::from(
+                path.parent()
+                    .map(|p| p.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            ),
+            is_image: !is_dir
+                && (is_image_suffix(&suffix) || (suffix.is_empty() && sniff_image(path))),
+            suffix: QString::from(suffix),
+            size: if is_dir { 0 } else { meta.len() },
+            is_dir,
+            modified: meta.modified().ok(),
+        }
+    }
+}
+
+/// Which entries the model exposes.
+#[derive(QEnum, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Filter {
+    #[default]
+    All,
+    Images,
+    Dirs,
+}
+
+/// Sort key for the model rows.
+#[derive(QEnum, Clone, Copy, Default, PartialEq, Eq)]
+#[qenum(cpp_name = "SortBy")]
+pub enum SortBy {
+    #[default]
+    Name,
+    Modified,
+    Size,
+    /// Like `Name`, but runs of digits compare numerically ("img2" < "img10").
+    Natural,
+}
+
+/// Model roles. `modelData` hands the whole `FileSystemEntry` to delegates.
+#[repr(i32)]
+enum Role {
+    ModelData = 0x0100 + 1,
+    Path,
+    RelativePath,
+    Name,
+    ParentDir,
+    Suffix,
+    Size,
+    IsDir,
+    IsImage,
+}
+
+#[derive(QObject)]
+#[qobject(base = "QAbstractListModel")]
+pub struct FileSystemModel {
+    #[qproperty]
+    path: QString,
+
+    #[qproperty]
+    recursive: bool,
+
+    #[qproperty]
+    filter: Filter,
+
+    #[qproperty(cpp_name = "watchChanges")]
+    watch_changes: bool,
+
+    #[qproperty(cpp_name = "showHidden")]
+    show_hidden: bool,
+
+    /// Glob patterns files must match, e.g. `["*.png", "*.jxl"]`. Entries may
+    /// also hold several `;`-separated patterns. Directories are not filtered.
+    #[qproperty(cpp_name = "nameFilters")]
+    name_filters: QStringList,
+
+    #[qproperty(cpp_name = "sortBy")]
+    sort_by: SortBy,
+
+    #[qproperty(cpp_name = "sortReverse")]
+    sort_reverse: bool,
+
+    #[qproperty(cpp_name = "dirsFirst")]
+    dirs_first: bool,
+
+    #[qproperty(read, notify = "entriesChanged")]
+    entries: Vec<FileSystemEntry>,
+
+    /// inotify watcher for `path`; dropping it stops the debounce thread.
+    watcher: Option<RecommendedWatcher>,
+    /// Bumped whenever the scan parameters change so stale scans started
+    /// with previous parameters are discarded.
+    generation: Arc<AtomicU64>,
+    /// Set while a rescan is queued, so setters called in a row (QML sets
+    /// several properties at once) only scan once.
+    update_pending: bool,
+}
+
+impl Default for FileSystemModel {
+    fn default() -> Self {
+        Self {
+            path: QString::default(),
+            recursive: false,
+            filter: Filter::All,
+            watch_changes: true,
+            show_hidden: false,
+            name_filters: QStringList::default(),
+            sort_by: SortBy::Name,
+            sort_reverse: false,
+            dirs_first: true,
+            entries: vec![],
+            watcher: None,
+            generation: Arc::new(AtomicU64::new(0)),
+            update_pending: false,
+        }
+    }
+}
+
+impl FileSystemModel {
+    #[qproperty(cpp_name = "path")]
+    pub fn set_path(&mut self, path: &QString) {
+        if &self.path == path {
+            return;
+        }
+        self.path = path.clone();
+        self.pathChanged();
+        self.schedule_update();
+    }
+
+    #[qproperty(cpp_name = "recursive")]
+    pub fn set_recursive(&mut self, recursive: bool) {
+        if self.recursive == recursive {
+            return;
+        }
+        self.recursive = recursive;
+        self.recursiveChanged();
+        self.schedule_update();
+    }
+
+    #[qproperty(cpp_name = "filter")]
+    pub fn set_filter(&mut self, filter: Filter) {
+        if self.filter == filter {
+            return;
+        }
+        self.filter = filter;
+        self.filterChanged();
+        self.schedule_update();
+    }
+
+    #[qproperty(cpp_name = "watchChanges")]
+    pub fn set_watch_changes(&mut self, watch: bool) {
+        if self.watch_changes == watch {
+            return;
+        }
+        self.watch_changes = watch;
+        self.watchChangesChanged();
+        self.update_watcher(self.scan_options());
+    }
+
+    #[qproperty(cpp_name = "showHidden")]
+    pub fn set_show_hidden(&mut self, show: bool) {
+        if self.show_hidden == show {
+            return;
+        }
+        self.show_hidden = show;
+        self.showHiddenChanged();
+        self.schedule_update();
+    }
+
+    #[qproperty(cpp_name = "nameFilters")]
+    pub fn set_name_filters(&mut self, filters: &QStringList) {
+        if &self.name_filters == filters {
+            return;
+        }
+        self.name_filters = filters.clone();
+        self.nameFiltersChanged();
+        self.schedule_update();
+    }
+
+    #[qproperty(cpp_name = "sortBy")]
+    pub fn set_sort_by(&mut self, sort_by: SortBy) {
+        if self.sort_by == sort_by {
+            return;
+        }
+        self.sort_by = sort_by;
+        self.sortByChanged();
+        self.schedule_update();
+    }
+
+    #[qproperty(cpp_name = "sortReverse")]
+    pub fn set_sort_reverse(&mut self, reverse: bool) {
+        if self.sort_reverse == reverse {
+            return;
+        }
+        self.sort_reverse = reverse;
+        self.sortReverseChanged();
+        self.schedule_update();
+    }
+
+    #[qproperty(cpp_name = "dirsFirst")]
+    pub fn set_dirs_first(&mut self, dirs_first: bool) {
+        if self.dirs_first == dirs_first {
+            return;
+        }
+        self.dirs_first = dirs_first;
+        self.dirsFirstChanged();
+        self.schedule_update();
+    }
+
+    #[cxx_qt::cxx_override]
+    pub fn row_count(&self, parent: &QModelIndex) -> i32 {
+        if parent.is_valid() {
+            return 0;
+        }
+        self.entries.len() as i32
+    }
+
+    #[cxx_qt::cxx_override]
+    pub fn data(&self, index: &QModelIndex, role: i32) -> QVariant {
+        let Some(entry) = self.entries.get(index.row() as usize) else {
+            return QVariant::default();
+        };
+        match role {
+            r if r == Role::ModelData as i32 => QVariant::from(entry),
+            r if r == Role::Path as i32 => QVariant::from(&entry.path),
+            r if r == Role::RelativePath as i32 => QVariant::from(&entry.relative_path),
+            r if r == Role::Name as i32 => QVariant::from(&entry.name),
+            r if r == Role::ParentDir as i32 => QVariant::from(&entry.parent_dir),
+            r if r == Role::Suffix as i32 => QVariant::from(&entry.suffix),
+            r if r == Role::Size as i32 => QVariant::from(&entry.size),
+            r if r == Role::IsDir as i32 => QVariant::from(&entry.is_dir),
+            r if r == Role::IsImage as i32 => QVariant::from(&entry.is_image),
+            _ => QVariant::default(),
+        }
+    }
+
+    #[cxx_qt::cxx_override]
+    pub fn role_names(&self) -> QHash<i32, QByteArray> {
+        let mut roles = QHash::default();
+        roles.insert(Role::ModelData as i32, QByteArray::from("modelData"));
+        roles.insert(Role::Path as i32, QByteArray::from("path"));
+        roles.insert(Role::RelativePath as i32, QByteArray::from("relativePath"));
+        roles.insert(Role::Name as i32, QByteArray::from("name"));
+        roles.insert(Role::ParentDir as i32, QByteArray::from("parentDir"));
+        roles.insert(Role::Suffix as i32, QByteArray::from("suffix"));
+        roles.insert(Role::Size as i32, QByteArray::from("size"));
+        roles.insert(Role::IsDir as i32, QByteArray::from("isDir"));
+        roles.insert(Role::IsImage as i32, QByteArray::from("isImage"));
+        roles
+    }
+
+    /// Rescan `path` on a worker thread and apply the difference to the
+    /// model once done.
+    #[qinvokable]
+    pub fn update(&mut self) {
+        self.update_pending = false;
+        let current = self.generation.fetch_add(1, Ordering::AcqRel) + 1;
+        let options = self.scan_options();
+        if options.root.as_os_str().is_empty() {
+            self.watcher = None;
+            self.apply_entries(vec![]);
+            return;
+        }
+
+        let generation = self.generation.clone();
+        let qt_thread = self.qt_thread();
+        let scan_options = options.clone();
+        thread::spawn(move || {
+            let cancelled = || generation.load(Ordering::Acquire) != current;
+            if let Some(entries) = scan(&scan_options, cancelled) {
+                queue_entries(&qt_thread, generation, current, entries);
+            }
+        });
+        self.update_watcher(options);
+    }
+
+    /// Run `update` once control returns to the event loop, folding any
+    /// further changes made until then into the same scan.
+    fn schedule_update(&mut self) {
+        if self.update_pending {
+            return;
+        }
+        self.update_pending = true;
+        let queued = self.qt_thread().queue(|model: &mut FileSystemModel| {
+            // `update` may have been invoked directly in the meantime.
+            if model.update_pending {
+                model.update();
+            }
+        });
+        if queued.is_err() {
+            self.update_pending = false;
+        }
+    }
+
+    fn scan_options(&self) -> ScanOptions {
+        let match_options = MatchOptions {
+            case_sensitive: false,
+            ..MatchOptions::new()
+        };
+        let name_filters = self
+            .name_filters
+            .iter()
+            .flat_map(|f| {
+                f.to_string()
+                    .split(';')
+                    .map(|p| p.trim().to_string())
+                    .filter(|p| !p.is_empty())
+                    .collect::<Vec<_>>()
+            })
+            .filter_map(|p| match Pattern::new(&p) {
+                Ok(pattern) => Some(pattern),
+                Err(e) => {
+                    eprintln!("FileSystemModel: invalid name filter {p}: {e}");
+                    None
+                }
+            })
+            .collect();
+        ScanOptions {
+            root: PathBuf::from(self.path.to_string()),
+            recursive: self.recursive,
+            filter: self.filter,
+            show_hidden: self.show_hidden,
+            name_filters,
+            match_options,
+            sort_by: self.sort_by,
+            sort_reverse: self.sort_reverse,
+            dirs_first: self.dirs_first,
+        }
+    }
+
+    /// (Re)create the inotify watcher for the current `path`. Events are
+    /// coalesced on a worker thread, which rescans and queues the result
+    /// back onto the Qt thread.
+    fn update_watcher(&mut self, options: ScanOptions) {
+        self.watcher = None;
+        if !self.watch_changes || options.root.as_os_str().is_empty() {
+            return;
+        }
+
+        let (tx, rx) = unbounded();
+        let mut watcher = match notify::recommended_watcher(move |res| {
+            let _ = tx.send(res);
+        }) {
+            Ok(w) => w,
+            Err(e) => {
+                eprintln!("FileSystemModel: unable to create watcher: {e}");
+                return;
+            }
+        };
+        let mode = if self.recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        if let Err(e) = watcher.watch(&options.root, mode) {
+            eprintln!(
+                "FileSystemModel: unable to watch {}: {e}",
+                options.root.display()
+            );
+            return;
+        }
+
+        let generation = self.generation.clone();
+        let current = generation.load(Ordering::Acquire);
+        let qt_thread = self.qt_thread();
+        thread::spawn(move || watch_loop(rx, qt_thread, generation, current, options));
+        self.watcher = Some(watcher);
+    }
+
+    /// Apply `new_entries` as row removals, insertions and data changes
+    /// rather than resetting the model, so views keep their state.
+    fn apply_entries(&mut self, new_entries: Vec<FileSystemEntry>) {
+        let parent = QModelIndex::default();
+        let changes = diff_entries(&self.entries, new_entries);
+        if changes.is_empty() {
+            return;
+        }
+        for change in changes {
+            match change {
+                RowChange::Reset(entries) => {
+                    self.begin_reset_model();
+                    self.entries = entries;
+                    self.end_reset_model();
+                }
+                RowChange::Remove(rows) => {
+                    self.begin_remove_rows(&parent, rows.start as i32, rows.end as i32 - 1);
+                    self.entries.drain(rows);
+                    self.end_remove_rows();
+                }
+                RowChange::Insert(row, entries) => {
+                    let last = row + entries.len() - 1;
+                    self.begin_insert_rows(&parent, row as i32, last as i32);
+                    self.entries.splice(row..row, entries);
+                    self.end_insert_rows();
+                }
+                RowChange::Update(row, entry) => {
+                    self.entries[row] = entry;
+                    let index = self.index(row as i32, 0, &parent);
+                    self.data_changed(&index, &index);
+                }
+            }
+        }
+        self.entriesChanged();
+    }
+
+    #[cxx_qt::qsignal]
+    fn entriesChanged(&self);
+}
+
+/// Wait for filesystem events, coalesce bursts and push a fresh scan to the
+/// model. Exits once the watcher (and with it the sender) is dropped.
+fn watch_loop(
+    rx: Receiver<notify::Result<Event>>,
+    qt_thread: CxxQtThread<FileSystemModel>,
+    generation: Arc<AtomicU64>,
+    current: u64,
+    options: ScanOptions,
+) {
+    while let Ok(first) = rx.recv() {
+        if !is_relevant(&first) {
+            continue;
+        }
+        let burst_start = Instant::now();
+        loop {
+            let remaining = WATCH_MAX_DELAY.saturating_sub(burst_start.elapsed());
+            if remaining.is_zero() {
+                break;
+            }
+            match rx.recv_timeout(WATCH_DEBOUNCE.min(remaining)) {
+                Ok(_) => continue,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+
+        if generation.load(Ordering::Acquire) != current {
+            return;
+        }
+        let Some(entries) = scan(&options, || generation.load(Ordering::Acquire) != current) else {
+            return;
+        };
+        if !queue_entries(&qt_thread, generation.clone(), current, entries) {
+            // The model has been destroyed.
+            return;
+        }
+    }
+}
+
+/// Hand scan results to the model on the Qt thread, unless the scan
+/// parameters changed in the meantime. Returns false if the model is gone.
+fn queue_entries(
+    qt_thread: &CxxQtThread<FileSystemModel>,
+    generation: Arc<AtomicU64>,
+    current: u64,
+    entries: Vec<FileSystemEntry>,
+) -> bool {
+    qt_thread
+        .queue(move |model: &mut FileSystemModel| {
+            if generation.load(Ordering::Acquire) == current {
+                model.apply_entries(entries);
+            }
+        })
+        .is_ok()
+}
+
+/// One step of turning the current rows into a new scan result. Row numbers
+/// refer to the list as left by the preceding steps.
+#[derive(Debug, PartialEq)]
+enum RowChange {
+    Reset(Vec<FileSystemEntry>),
+    Remove(Range<usize>),
+    /// Insert the entries before the given row.
+    Insert(usize, Vec<FileSystemEntry>),
+    Update(usize, FileSystemEntry),
+}
+
+/// Work out the steps turning `old` into `new`: stale rows are removed in
+/// contiguous runs, new ones inserted between the survivors and changed
+/// survivors updated. Falls back to a reset when the sort order of surviving
+/// rows changed.
+fn diff_entries(old: &[FileSystemEntry], new: Vec<FileSystemEntry>) -> Vec<RowChange> {
+    if !preserves_order(old, &new) {
+        return vec![RowChange::Reset(new)];
+    }
+
+    let mut changes = vec![];
+    let mut kept = Vec::with_capacity(old.len());
+    {
+        let new_paths: HashSet<&QString> = new.iter().map(|e| &e.path).collect();
+        // Back to front, so earlier row numbers stay valid.
+        let mut row = old.len();
+        while row > 0 {
+            let end = row;
+            while row > 0 && !new_paths.contains(&old[row - 1].path) {
+                row -= 1;
+            }
+            if row < end {
+                changes.push(RowChange::Remove(row..end));
+            } else {
+                row -= 1;
+                kept.push(&old[row]);
+            }
+        }
+        kept.reverse();
+    }
+
+    // Surviving rows are already in order, so new entries slot in between
+    // them.
+    let mut kept = kept.into_iter().peekable();
+    let mut row = 0;
+    let mut pending = vec![];
+    for entry in new {
+        if let Some(current) = kept.next_if(|e| e.path == entry.path) {
+            push_insert(&mut changes, &mut row, &mut pending);
+            if *current != entry {
+                changes.push(RowChange::Update(row, entry));
+            }
+            row += 1;
+        } else {
+            pending.push(entry);
+        }
+    }
+    push_insert(&mut changes, &mut row, &mut pending);
+    changes
+}
+
+/// Queue a run of consecutive new rows before `row` and move past them.
+fn push_insert(changes: &mut Vec<RowChange>, row: &mut usize, pending: &mut Vec<FileSystemEntry>) {
+    if pending.is_empty() {
+        return;
+    }
+    let count = pending.len();
+    changes.push(RowChange::Insert(*row, std::mem::take(pending)));
+    *row += count;
+}
+
+/// Whether the rows present in both lists appear in the same relative order.
+fn preserves_order(old: &[FileSystemEntry], new: &[FileSystemEntry]) -> bool {
+    let positions: HashMap<&QString, usize> =
+        new.iter().enumerate().map(|(i, e)| (&e.path, i)).collect();
+    let mut last = None;
+    for entry in old {
+        if let Some(&pos) = positions.get(&entry.path) {
+            if last.is_some_and(|l| l > pos) {
+                return false;
+            }
+            last = Some(pos);
+        }
+    }
+    true
+}
+
+fn is_relevant(event: &notify::Result<Event>) -> bool {
+    match event {
+        Ok(event) => !matches!(event.kind, EventKind::Access(_)),
+        Err(_) => true,
+    }
+}
+
+fn is_image_suffix(suffix: &str) -> bool {
+    IMAGE_EXTENSIONS
+        .iter()
+        .any(|ext| ext.eq_ignore_ascii_case(suffix))
+}
+
+/// Check the magic bytes of an extension-less file for a known image format.
+fn sniff_image(path: &Path) -> bool {
+    matches!(
+        infer::get_from_path(path),
+        Ok(Some(kind)) if kind.matcher_type() == infer::MatcherType::Image
+    )
+}
+
+/// Compare names, treating runs of ASCII digits as numbers.
+fn natural_cmp(a: &str, b: &str) -> CmpOrdering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return CmpOrdering::Equal,
+            (None, Some(_)) => return CmpOrdering::Less,
+            (Some(_), None) => return CmpOrdering::Greater,
+            (Some(ca), Some(cb)) if ca.is_ascii_digit() && cb.is_ascii_digit() => {
+                let na: String = std::iter::from_fn(|| a.next_if(|c| c.is_ascii_digit())).collect();
+                let nb: String = std::iter::from_fn(|| b.next_if(|c| c.is_ascii_digit())).collect();
+                let ta = na.trim_start_matches('0');
+                let tb = nb.trim_start_matches('0');
+                let ord = ta.len().cmp(&tb.len()).then_with(|| ta.cmp(tb));
+                if ord != CmpOrdering::Equal {
+                    return ord;
+                }
+            }
+            (Some(ca), Some(cb)) => {
+                let ord = ca.to_lowercase().cmp(cb.to_lowercase());
+                if ord != CmpOrdering::Equal {
+                    return ord;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+/// Everything a scan needs, detached from the model so it can run on a
+/// worker thread.
+#[derive(Clone)]
+struct ScanOptions {
+    root: PathBuf,
+    recursive: bool,
+    filter: Filter,
+    show_hidden: bool,
+    name_filters: Vec<Pattern>,
+    match_options: MatchOptions,
+    sort_by: SortBy,
+    sort_reverse: bool,
+    dirs_first: bool,
+}
+
+impl ScanOptions {
+    fn matches(&self, entry: &FileSystemEntry) -> bool {
+        let kind = match self.filter {
+            Filter::All => true,
+            Filter::Images => entry.is_image,
+            Filter::Dirs => entry.is_dir,
+        };
+        kind && (entry.is_dir
+            || self.name_filters.is_empty()
+            || self
+                .name_filters
+                .iter()
+                .any(|p| p.matches_with(&entry.name.to_string(), self.match_options)))
+    }
+
+    fn compare(&self, a: &FileSystemEntry, b: &FileSystemEntry) -> CmpOrdering {
+        if self.dirs_first && a.is_dir != b.is_dir {
+            return b.is_dir.cmp(&a.is_dir);
+        }
+        let ord = match self.sort_by {
+            SortBy::Name => a.relative_path.cmp(&b.relative_path),
+            SortBy::Modified => a.modified.cmp(&b.modified),
+            SortBy::Size => a.size.cmp(&b.size),
+            SortBy::Natural => {
+                natural_cmp(&a.relative_path.to_string(), &b.relative_path.to_string())
+            }
+        }
+        // Keep the order total so incremental updates stay stable.
+        .then_with(|| a.path.cmp(&b.path));
+        if self.sort_reverse {
+            ord.reverse()
+        } else {
+            ord
+        }
+    }
+}
+
+/// Walk the scan root (descending into subdirectories when `recursive` is
+/// set) and collect the matching entries in display order. Symlinks are
+/// listed as their target but never descended into, so link cycles cannot
+/// make the walk repeat itself. Returns `None` once `cancelled` reports that
+/// the result is no longer wanted.
+fn scan(options: &ScanOptions, cancelled: impl Fn() -> bool) -> Option<Vec<FileSystemEntry>> {
+    let mut entries = vec![];
+    let mut dirs = vec![options.root.clone()];
+    while let Some(dir) = dirs.pop() {
+        if cancelled() {
+            return None;
+        }
+        let Ok(read_dir) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for dirent in read_dir.flatten() {
+            if !options.show_hidden && dirent.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let Ok(file_type) = dirent.file_type() else {
+                continue;
+            };
+            let path = dirent.path();
+            // Skips dangling links.
+            let Ok(meta) = fs::metadata(&path) else {
+                continue;
+            };
+            if options.recursive && file_type.is_dir() {
+                dirs.push(path.clone());
+            }
+            let entry = FileSystemEntry::new(&options.root, &path, &meta);
+            if options.matches(&entry) {
+                entries.push(entry);
+            }
+        }
+    }
+    entries.sort_by(|a, b| options.compare(a, b));
+    Some(entries)
+}
+
+pub fn register() {
+    cxx_qt::qml_register_type::<FileSystemEntry>("Vela", 1, 0, "FileSystemEntry");
+    cxx_qt::qml_register_type::<FileSystemModel>("Vela", 1, 0, "FileSystemModel");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, size: u64) -> FileSystemEntry {
+        FileSystemEntry {
+            path: QString::from(path),
+            name: QString::from(path),
+            size,
+            ..Default::default()
+        }
+    }
+
+    fn entries(paths: &[&str]) -> Vec<FileSystemEntry> {
+        paths.iter().map(|p| entry(p, 0)).collect()
+    }
+
+    /// Replay `changes` the way `apply_entries` does.
+    fn replay(mut rows: Vec<FileSystemEntry>, changes: Vec<RowChange>) -> Vec<FileSystemEntry> {
+        for change in changes {
+            match change {
+                RowChange::Reset(entries) => rows = entries,
+                RowChange::Remove(range) => {
+                    assert!(range.start < range.end && range.end <= rows.len());
+                    rows.drain(range);
+                }
+                RowChange::Insert(row, entries) => {
+                    assert!(!entries.is_empty() && row <= rows.len());
+                    rows.splice(row..row, entries);
+                }
+                RowChange::Update(row, entry) => {
+                    assert_eq!(rows[row].path, entry.path);
+                    rows[row] = entry;
+                }
+            }
+        }
+        rows
+    }
+
+    #[test]
+    fn identical_rows_need_no_changes() {
+        let rows = entries(&["a", "b", "c"]);
+        assert!(diff_entries(&rows, rows.clone()).is_empty());
+    }
+
+    #[test]
+    fn removals_are_grouped_into_runs() {
+        let old = entries(&["a", "b", "c", "d", "e", "f"]);
+        let new = entries(&["a", "d", "f"]);
+        let changes = diff_entries(&old, new.clone());
+        assert_eq!(
+            changes,
+            vec![RowChange::Remove(4..5), RowChange::Remove(1..3)]
+        );
+        assert_eq!(replay(old, changes), new);
+    }
+
+    #[test]
+    fn insertions_slot_in_between_survivors() {
+        let old = entries(&["b", "d"]);
+        let new = entries(&["a", "b", "c", "d", "e", "f"]);
+        let changes = diff_entries(&old, new.clone());
+        assert_eq!(
+            changes,
+            vec![
+                RowChange::Insert(0, entries(&["a"])),
+                RowChange::Insert(2, entries(&["c"])),
+                RowChange::Insert(4, entries(&["e", "f"])),
+            ]
+        );
+        assert_eq!(replay(old, changes), new);
+    }
+
+    #[test]
+    fn changed_rows_are_updated_in_place() {
+        let old = entries(&["a", "b", "c"]);
+        let new = vec![entry("a", 0), entry("b", 42), entry("c", 0)];
+        let changes = diff_entries(&old, new.clone());
+        assert_eq!(changes, vec![RowChange::Update(1, entry("b", 42))]);
+        assert_eq!(replay(old, changes), new);
+    }
+
+    #[test]
+    fn mixed_changes_produce_the_new_rows() {
+        let old = entries(&["a", "b", "c", "d", "e"]);
+        let new = vec![
+            entry("0", 0),
+            entry("b", 0),
+            entry("bb", 0),
+            entry("d", 7),
+            entry("z", 0),
+        ];
+        let changes = diff_entries(&old, new.clone());
+        assert!(changes.iter().all(|c| !matches!(c, RowChange::Reset(_))));
+        assert_eq!(replay(old, changes), new);
+
+        let all = entries(&["a", "b"]);
+        assert_eq!(replay(all.clone(), diff_entries(&all, vec![])), vec![]);
+        assert_eq!(replay(vec![], diff_entries(&[], new.clone())), new);
+    }
+
+    #[test]
+    fn reordered_rows_reset_the_model() {
+        let old = entries(&["a", "b", "c"]);
+        let new = entries(&["c", "b", "x"]);
+        assert_eq!(diff_entries(&old, new.clone()), vec![RowChange::Reset(new)]);
+    }
+
+    fn scan_options(root: PathBuf) -> ScanOptions {
+        ScanOptions {
+            root,
+            recursive: true,
+            filter: Filter::All,
+            show_hidden: false,
+            name_filters: vec![],
+            match_options: MatchOptions::new(),
+            sort_by: SortBy::Name,
+            sort_reverse: false,
+            dirs_first: false,
+        }
+    }
+
+    #[test]
+    fn scan_does_not_follow_symlinked_dirs() {
+        let root = std::env::temp_dir().join(format!("vela-fsmodel-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("sub/a.png"), b"").unwrap();
+        std::os::unix::fs::symlink(".", root.join("loop")).unwrap();
+        std::os::unix::fs::symlink("sub", root.join("link")).unwrap();
+        std::os::unix::fs::symlink("missing", root.join("dangling")).unwrap();
+
+        let found = scan(&scan_options(root.clone()), || false).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        let names: Vec<String> = found.iter().map(|e| e.relative_path.to_string()).collect();
+        assert_eq!(names, ["link", "loop", "sub", "sub/a.png"]);
+        // Links are described by their target.
+        assert!(found[0].is_dir && found[1].is_dir);
+    }
+
+    #[test]
+    fn cancelled_scan_returns_nothing() {
+        assert!(scan(&scan_options(std::env::temp_dir()), || true).is_none());
+    }
+}
@@ -0,0 +1,333 @@
+use image::imageops::FilterType;
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::url;
+
+/// Thumbnail buckets from the freedesktop thumbnail spec, smallest first.
+pub const BUCKETS: &[(&str, u32)] = &[
+    ("normal", 128),
+    ("large", 256),
+    ("x-large", 512),
+    ("xx-large", 1024),
+];
+
+/// Default cap on the total size of all buckets.
+pub const DEFAULT_MAX_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Bytes written since the last sweep before another one is scheduled.
+const SWEEP_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+static WRITTEN_SINCE_SWEEP: AtomicU64 = AtomicU64::new(0);
+static SWEPT_ONCE: AtomicBool = AtomicBool::new(false);
+static SWEEPING: AtomicBool = AtomicBool::new(false);
+
+/// Smallest bucket that covers `size` pixels, if any.
+pub fn bucket_for(size: u32) -> Option<(&'static str, u32)> {
+    BUCKETS.iter().copied().find(|&(_, s)| s >= size)
+}
+
+/// Source URI as required by the thumbnail spec for hashing and `Thumb::URI`.
+fn file_uri(path: &Path) -> String {
+    format!("file://{}", url::percent_encode(path.as_os_str().as_bytes()))
+}
+
+/// Inverse of `file_uri`.
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    uri.starts_with("file://").then(|| url::local_path(uri)).flatten()
+}
+
+fn mtime_secs(meta: &fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Return the thumbnail for `source` in `dir`, (re)generating it when it is
+/// missing or its recorded mtime/size no longer match the source file.
+pub fn ensure_thumbnail(source: &Path, dir: &Path, size: u32) -> Result<PathBuf, String> {
+    let meta = fs::metadata(source).map_err(|e| e.to_string())?;
+    let mtime = mtime_secs(&meta);
+    let uri = file_uri(source);
+    let thumb = dir.join(format!("{:x}.png", md5::compute(uri.as_bytes())));
+
+    if let Some(info) = ThumbInfo::read(&thumb) {
+        if info.uri == uri && info.mtime == mtime && info.size.is_none_or(|s| s == meta.len()) {
+            touch(&thumb);
+            return Ok(thumb);
+        }
+    }
+
+    let img = image::open(source).map_err(|e| e.to_string())?;
+    let resized = if img.width().max(img.height()) > size {
+        img.resize(size, size, FilterType::Triangle)
+    } else {
+        img
+    };
+    let rgba = resized.to_rgba8();
+
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    // Write to a temp file and rename so readers never see a partial PNG.
+    static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+    let tmp = dir.join(format!(
+        ".{}-{}.tmp",
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    {
+        let file = File::create(&tmp).map_err(|e| e.to_string())?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), rgba.width(), rgba.height());
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        for (key, value) in [
+            ("Thumb::URI", uri.clone()),
+            ("Thumb::MTime", mtime.to_string()),
+            ("Thumb::Size", meta.len().to_string()),
+            ("Software", "vela".to_string()),
+        ] {
+            encoder
+                .add_text_chunk(key.to_string(), value)
+                .map_err(|e| e.to_string())?;
+        }
+        let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+        writer
+            .write_image_data(rgba.as_raw())
+            .map_err(|e| e.to_string())?;
+    }
+    fs::rename(&tmp, &thumb).map_err(|e| e.to_string())?;
+
+    let written = fs::metadata(&thumb).map_or(0, |m| m.len());
+    WRITTEN_SINCE_SWEEP.fetch_add(written, Ordering::Relaxed);
+    Ok(thumb)
+}
+
+/// Mark a thumbnail as recently used. The thumbnail's own mtime is the LRU
+/// clock; the source mtime lives in `Thumb::MTime`.
+fn touch(thumb: &Path) {
+    if let Ok(file) = File::options().write(true).open(thumb) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+}
+
+/// The `Thumb::*` text chunks of a cached thumbnail.
+struct ThumbInfo {
+    uri: String,
+    mtime: u64,
+    size: Option<u64>,
+}
+
+impl ThumbInfo {
+    fn read(thumb: &Path) -> Option<Self> {
+        let file = File::open(thumb).ok()?;
+        let reader = png::Decoder::new(BufReader::new(file)).read_info().ok()?;
+        let text = &reader.info().uncompressed_latin1_text;
+        let get = |key: &str| text.iter().find(|t| t.keyword == key).map(|t| t.text.clone());
+        Some(Self {
+            uri: get("Thumb::URI")?,
+            mtime: get("Thumb::MTime")?.parse().ok()?,
+            size: get("Thumb::Size").and_then(|s| s.parse().ok()),
+        })
+    }
+
+    /// Whether the source file is gone or has changed since thumbnailing.
+    fn is_stale(&self) -> bool {
+        let Some(source) = uri_to_path(&self.uri) else {
+            return true;
+        };
+        match fs::metadata(source) {
+            Ok(meta) => {
+                mtime_secs(&meta) != self.mtime || self.size.is_some_and(|s| s != meta.len())
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+/// Whether enough has been written (or nothing swept yet this session) to
+/// warrant a sweep. Claims the sweep so only one runs at a time.
+pub fn should_sweep() -> bool {
+    let due = !SWEPT_ONCE.load(Ordering::Relaxed)
+        || WRITTEN_SINCE_SWEEP.load(Ordering::Relaxed) >= SWEEP_THRESHOLD;
+    due && !SWEEPING.swap(true, Ordering::AcqRel)
+}
+
+/// Remove stale thumbnails, then evict least recently used ones until the
+/// cache fits in `max_bytes`. Must be preceded by a successful `should_sweep`.
+pub fn sweep(cache_dir: &Path, max_bytes: u64) {
+    let mut live = vec![];
+    let mut total = 0u64;
+    for path in thumbnails(cache_dir) {
+        let Ok(meta) = fs::metadata(&path) else {
+            continue;
+        };
+        if ThumbInfo::read(&path).is_none_or(|info| info.is_stale()) {
+            let _ = fs::remove_file(&path);
+            continue;
+        }
+        total += meta.len();
+        live.push((meta.modified().unwrap_or(UNIX_EPOCH), meta.len(), path));
+    }
+
+    live.sort_by_key(|(used, _, _)| *used);
+    for (_, len, path) in live {
+        if total <= max_bytes {
+            break;
+        }
+        if fs::remove_file(&path).is_ok() {
+            total -= len;
+        }
+    }
+
+    WRITTEN_SINCE_SWEEP.store(0, Ordering::Relaxed);
+    SWEPT_ONCE.store(true, Ordering::Relaxed);
+    SWEEPING.store(false, Ordering::Release);
+}
+
+/// Delete every cached thumbnail. Returns the number of bytes freed.
+pub fn purge(cache_dir: &Path) -> u64 {
+    let mut freed = 0;
+    for path in thumbnails(cache_dir) {
+        let len = fs::metadata(&path).map_or(0, |m| m.len());
+        if fs::remove_file(&path).is_ok() {
+            freed += len;
+        }
+    }
+    WRITTEN_SINCE_SWEEP.store(0, Ordering::Relaxed);
+    freed
+}
+
+/// All thumbnail files across the buckets.
+fn thumbnails(cache_dir: &Path) -> Vec<PathBuf> {
+    BUCKETS
+        .iter()
+        .filter_map(|(bucket, _)| fs::read_dir(cache_dir.join(bucket)).ok())
+        .flat_map(|dir| dir.flatten().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|e| e == "png"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use std::time::Duration;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("vela-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn write_source(path: &Path, size: u32) {
+        RgbaImage::from_pixel(size, size, Rgba([200, 30, 30, 255]))
+            .save(path)
+            .unwrap();
+    }
+
+    fn set_used(thumb: &Path, ago: Duration) {
+        File::options()
+            .write(true)
+            .open(thumb)
+            .unwrap()
+            .set_modified(SystemTime::now() - ago)
+            .unwrap();
+    }
+
+    #[test]
+    fn thumbnails_go_stale_with_their_source() {
+        let tmp = TempDir::new("thumb-stale");
+        let source = tmp.0.join("a b.png");
+        write_source(&source, 8);
+        let thumb = ensure_thumbnail(&source, &tmp.0.join("normal"), 128).unwrap();
+        let info = ThumbInfo::read(&thumb).unwrap();
+        assert_eq!(uri_to_path(&info.uri), Some(source.clone()));
+        assert!(!info.is_stale());
+
+        // A rewrite within the same second is still caught by the size.
+        write_source(&source, 16);
+        assert!(ThumbInfo::read(&thumb).unwrap().is_stale());
+        let regenerated = ensure_thumbnail(&source, &tmp.0.join("normal"), 128).unwrap();
+        assert_eq!(regenerated, thumb);
+        assert!(!ThumbInfo::read(&thumb).unwrap().is_stale());
+
+        fs::remove_file(&source).unwrap();
+        assert!(ThumbInfo::read(&thumb).unwrap().is_stale());
+    }
+
+    #[test]
+    fn sweep_evicts_least_recently_used_first() {
+        let tmp = TempDir::new("thumb-sweep");
+        let cache = tmp.0.join("cache");
+        let thumbs: Vec<PathBuf> = (0..3)
+            .map(|i| {
+                let source = tmp.0.join(format!("{i}.png"));
+                write_source(&source, 8);
+                ensure_thumbnail(&source, &cache.join("normal"), 128).unwrap()
+            })
+            .collect();
+        // The first thumbnail was used most recently, the last longest ago.
+        for (i, thumb) in thumbs.iter().enumerate() {
+            set_used(thumb, Duration::from_secs(60 * (i as u64 + 1)));
+        }
+        let len = |p: &PathBuf| fs::metadata(p).unwrap().len();
+        let total: u64 = thumbs.iter().map(len).sum();
+
+        sweep(&cache, total);
+        assert!(thumbs.iter().all(|t| t.exists()));
+
+        sweep(&cache, total - 1);
+        assert!(thumbs[0].exists() && thumbs[1].exists());
+        assert!(!thumbs[2].exists());
+
+        sweep(&cache, len(&thumbs[0]));
+        assert!(thumbs[0].exists());
+        assert!(!thumbs[1].exists());
+    }
+
+    #[test]
+    fn sweep_removes_stale_thumbnails_regardless_of_size() {
+        let tmp = TempDir::new("thumb-sweep-stale");
+        let cache = tmp.0.join("cache");
+        let kept = tmp.0.join("kept.png");
+        let gone = tmp.0.join("gone.png");
+        write_source(&kept, 8);
+        write_source(&gone, 8);
+        let kept_thumb = ensure_thumbnail(&kept, &cache.join("large"), 256).unwrap();
+        let gone_thumb = ensure_thumbnail(&gone, &cache.join("large"), 256).unwrap();
+        // Unrelated files in a bucket are left alone.
+        let other = cache.join("large/notes.txt");
+        fs::write(&other, "x").unwrap();
+        fs::remove_file(&gone).unwrap();
+
+        sweep(&cache, u64::MAX);
+        assert!(kept_thumb.exists());
+        assert!(!gone_thumb.exists());
+        assert!(other.exists());
+    }
+
+    #[test]
+    fn bucket_for_picks_the_smallest_covering_bucket() {
+        assert_eq!(bucket_for(1), Some(("normal", 128)));
+        assert_eq!(bucket_for(128), Some(("normal", 128)));
+        assert_eq!(bucket_for(129), Some(("large", 256)));
+        assert_eq!(bucket_for(1024), Some(("xx-large", 1024)));
+        assert_eq!(bucket_for(1025), None);
+    }
+}
@@ -0,0 +1,166 @@
+use image::codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder};
+use image::imageops::FilterType;
+use image::{AnimationDecoder, DynamicImage, ImageFormat, ImageReader};
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+
+/// Longest side sampled animation frames are kept at; analysis downscales
+/// far below this anyway.
+const FRAME_SIZE: u32 = 512;
+
+/// Longest side SVGs are rasterised at.
+const SVG_SIZE: f32 = 512.0;
+
+/// Why an image could not be analysed.
+#[derive(Debug)]
+pub(crate) enum LoadError {
+    NotFound,
+    Unsupported(String),
+    Decode(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::NotFound => write!(f, "file not found"),
+            LoadError::Unsupported(what) => write!(f, "unsupported format: {what}"),
+            LoadError::Decode(e) => write!(f, "decode failed: {e}"),
+        }
+    }
+}
+
+fn decode_err(e: impl fmt::Display) -> LoadError {
+    LoadError::Decode(e.to_string())
+}
+
+/// Decode `path`, returning up to `max_frames` frames spread evenly across
+/// an animation, or the single frame of a still image. Handles everything
+/// `image` does plus SVG, JPEG XL, and animated GIF, WebP and APNG.
+pub(crate) fn load_frames(path: &Path, max_frames: usize) -> Result<Vec<DynamicImage>, LoadError> {
+    if !path.exists() {
+        return Err(LoadError::NotFound);
+    }
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "svg" | "svgz" => return load_svg(path).map(|img| vec![img]),
+        "jxl" => return load_jxl(path).map(|img| vec![img]),
+        _ => {}
+    }
+
+    let reader = ImageReader::open(path)
+        .map_err(decode_err)?
+        .with_guessed_format()
+        .map_err(decode_err)?;
+    let frames = match reader.format() {
+        Some(ImageFormat::Gif) => {
+            let decoder = GifDecoder::new(open(path)?).map_err(decode_err)?;
+            sample(decoder.into_frames(), max_frames)?
+        }
+        Some(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(open(path)?).map_err(decode_err)?;
+            if decoder.has_animation() {
+                sample(decoder.into_frames(), max_frames)?
+            } else {
+                vec![DynamicImage::from_decoder(decoder).map_err(decode_err)?]
+            }
+        }
+        Some(ImageFormat::Png) => {
+            let decoder = PngDecoder::new(open(path)?).map_err(decode_err)?;
+            if decoder.is_apng().map_err(decode_err)? {
+                sample(decoder.apng().map_err(decode_err)?.into_frames(), max_frames)?
+            } else {
+                vec![DynamicImage::from_decoder(decoder).map_err(decode_err)?]
+            }
+        }
+        Some(_) => vec![reader.decode().map_err(decode_err)?],
+        None => {
+            // Fall back to sniffing for formats `image` cannot guess.
+            return match infer::get_from_path(path) {
+                Ok(Some(kind)) if kind.mime_type() == "image/jxl" => {
+                    load_jxl(path).map(|img| vec![img])
+                }
+                Ok(Some(kind)) => Err(LoadError::Unsupported(kind.mime_type().to_string())),
+                _ => Err(LoadError::Unsupported(ext)),
+            };
+        }
+    };
+    if frames.is_empty() {
+        return Err(LoadError::Decode("no frames".to_string()));
+    }
+    Ok(frames)
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, LoadError> {
+    File::open(path).map(BufReader::new).map_err(decode_err)
+}
+
+/// Keep at most `max_frames` frames, evenly spaced over the animation.
+/// Frames have to be decoded in order, but only every `stride`-th one is
+/// kept; once too many are kept every other one is dropped and the stride
+/// doubled, so memory stays bounded however long the animation is.
+fn sample(frames: image::Frames<'_>, max_frames: usize) -> Result<Vec<DynamicImage>, LoadError> {
+    let max_frames = max_frames.max(1);
+    let mut kept = Vec::with_capacity(max_frames + 1);
+    let mut stride = 1;
+    for (i, frame) in frames.enumerate() {
+        let frame = frame.map_err(decode_err)?;
+        if i % stride != 0 {
+            continue;
+        }
+        let mut img = DynamicImage::ImageRgba8(frame.into_buffer());
+        if img.width().max(img.height()) > FRAME_SIZE {
+            img = img.resize(FRAME_SIZE, FRAME_SIZE, FilterType::Triangle);
+        }
+        kept.push(img);
+        if kept.len() > max_frames {
+            let mut index = 0;
+            kept.retain(|_| {
+                index += 1;
+                index % 2 == 1
+            });
+            stride *= 2;
+        }
+    }
+    Ok(kept)
+}
+
+fn load_svg(path: &Path) -> Result<DynamicImage, LoadError> {
+    let data = fs::read(path).map_err(decode_err)?;
+    let tree = resvg::usvg::Tree::from_data(&data, &resvg::usvg::Options::default())
+        .map_err(decode_err)?;
+    let size = tree.size();
+    let scale = SVG_SIZE / size.width().max(size.height());
+    let width = (size.width() * scale).ceil().max(1.0) as u32;
+    let height = (size.height() * scale).ceil().max(1.0) as u32;
+    let mut pixmap = resvg::tiny_skia::Pixmap::new(width, height)
+        .ok_or_else(|| LoadError::Decode("empty SVG".to_string()))?;
+    resvg::render(
+        &tree,
+        resvg::tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+    // tiny-skia stores premultiplied alpha.
+    let mut rgba = pixmap.take();
+    for px in rgba.chunks_exact_mut(4) {
+        let a = px[3] as u32;
+        if a > 0 && a < 255 {
+            for c in &mut px[..3] {
+                *c = ((*c as u32 * 255 + a / 2) / a).min(255) as u8;
+            }
+        }
+    }
+    image::RgbaImage::from_raw(width, height, rgba)
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_else(|| LoadError::Decode("invalid SVG raster".to_string()))
+}
+
+fn load_jxl(path: &Path) -> Result<DynamicImage, LoadError> {
+    let decoder = jxl_oxide::integration::JxlDecoder::new(open(path)?).map_err(decode_err)?;
+    DynamicImage::from_decoder(decoder).map_err(decode_err)
+}
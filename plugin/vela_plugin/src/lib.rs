@@ -1,10 +1,25 @@
+mod analyser;
 mod app_entry;
 mod appdb;
 mod audio_collector;
+mod beat_tracker;
+mod caching_image_manager;
+mod cava_provider;
 mod cutils;
+mod exchange_rates;
+mod file_ops;
+mod file_system_model;
+mod image_cache;
+mod image_loader;
+mod palette;
+mod qalc;
+mod qalc_scan;
 mod qalculator;
+mod scheme;
 mod service;
 mod service_ref;
+mod trash;
+mod url;
 
 #[cxx::bridge(namespace = "Vela")]
 mod ffi {
@@ -21,4 +36,9 @@ pub fn register_types() {
     service::register();
     service_ref::register();
     audio_collector::register();
+    file_system_model::register();
+    scheme::register();
+    caching_image_manager::register();
+    cava_provider::register();
+    beat_tracker::register();
 }
@@ -0,0 +1,192 @@
+use color_thief::{get_palette, ColorFormat};
+use image::{imageops::FilterType, DynamicImage};
+
+/// Side length images are downscaled to before quantizing.
+pub(crate) const SAMPLE_SIZE: u32 = 128;
+
+/// Relative weights of saturation, lightness and population when scoring a
+/// swatch against a role target.
+const WEIGHT_SATURATION: f64 = 0.24;
+const WEIGHT_LIGHTNESS: f64 = 0.52;
+const WEIGHT_POPULATION: f64 = 0.24;
+
+/// A quantized color and how many sampled pixels it represents.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct Swatch {
+    pub rgb: [u8; 3],
+    pub population: u32,
+}
+
+impl Swatch {
+    fn hsl(&self) -> (f64, f64, f64) {
+        rgb_to_hsl(self.rgb)
+    }
+}
+
+/// Material-You-style role a swatch can fill.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Role {
+    Vibrant,
+    LightVibrant,
+    DarkVibrant,
+    Muted,
+    LightMuted,
+    DarkMuted,
+}
+
+impl Role {
+    /// Roles in the order they claim swatches.
+    pub const ALL: [Role; 6] = [
+        Role::Vibrant,
+        Role::LightVibrant,
+        Role::DarkVibrant,
+        Role::Muted,
+        Role::LightMuted,
+        Role::DarkMuted,
+    ];
+
+    /// Key used for the role in the QML result.
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Vibrant => "vibrant",
+            Role::LightVibrant => "lightVibrant",
+            Role::DarkVibrant => "darkVibrant",
+            Role::Muted => "muted",
+            Role::LightMuted => "lightMuted",
+            Role::DarkMuted => "darkMuted",
+        }
+    }
+
+    /// (min, target, max) for saturation and lightness.
+    fn target(self) -> ((f64, f64, f64), (f64, f64, f64)) {
+        let vibrant = (0.35, 1.0, 1.0);
+        let muted = (0.0, 0.3, 0.4);
+        let light = (0.55, 0.74, 1.0);
+        let normal = (0.3, 0.5, 0.7);
+        let dark = (0.0, 0.26, 0.45);
+        match self {
+            Role::Vibrant => (vibrant, normal),
+            Role::LightVibrant => (vibrant, light),
+            Role::DarkVibrant => (vibrant, dark),
+            Role::Muted => (muted, normal),
+            Role::LightMuted => (muted, light),
+            Role::DarkMuted => (muted, dark),
+        }
+    }
+}
+
+pub(crate) struct Palette {
+    /// Swatches ordered by descending population.
+    pub swatches: Vec<Swatch>,
+    /// The best swatch for each role, if any fits.
+    pub roles: Vec<(Role, Option<Swatch>)>,
+}
+
+impl Palette {
+    pub fn total_population(&self) -> u32 {
+        self.swatches.iter().map(|s| s.population).sum()
+    }
+}
+
+/// Quantize `img` into at most `count` swatches and pick role candidates.
+pub(crate) fn extract(img: &DynamicImage, count: usize) -> Palette {
+    let rgb = img
+        .resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle)
+        .to_rgb8();
+    let pixels = rgb.as_raw();
+
+    // color_thief needs at least two colors and yields no populations, so
+    // count them by assigning each pixel to its nearest swatch.
+    let colors = get_palette(pixels, ColorFormat::Rgb, 1, count.clamp(2, 255) as u8)
+        .unwrap_or_default();
+    let mut swatches: Vec<Swatch> = colors
+        .iter()
+        .take(count)
+        .map(|c| Swatch {
+            rgb: [c.r, c.g, c.b],
+            population: 0,
+        })
+        .collect();
+    if !swatches.is_empty() {
+        for px in pixels.chunks_exact(3) {
+            let nearest = swatches
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, s)| distance_sq(s.rgb, [px[0], px[1], px[2]]))
+                .map(|(i, _)| i)
+                .unwrap_or(0);
+            swatches[nearest].population += 1;
+        }
+    }
+    swatches.retain(|s| s.population > 0);
+    swatches.sort_by(|a, b| b.population.cmp(&a.population));
+
+    let max_population = swatches.first().map_or(1, |s| s.population.max(1));
+    let mut used = vec![false; swatches.len()];
+    let roles = Role::ALL
+        .iter()
+        .map(|&role| {
+            let best = best_for(role, &swatches, &used, max_population);
+            if let Some(i) = best {
+                used[i] = true;
+            }
+            (role, best.map(|i| swatches[i]))
+        })
+        .collect();
+
+    Palette { swatches, roles }
+}
+
+/// Index of the unused swatch scoring highest for `role`.
+fn best_for(
+    role: Role,
+    swatches: &[Swatch],
+    used: &[bool],
+    max_population: u32,
+) -> Option<usize> {
+    let ((min_s, target_s, max_s), (min_l, target_l, max_l)) = role.target();
+    swatches
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| !used[i])
+        .filter_map(|(i, swatch)| {
+            let (_, s, l) = swatch.hsl();
+            if !(min_s..=max_s).contains(&s) || !(min_l..=max_l).contains(&l) {
+                return None;
+            }
+            let score = WEIGHT_SATURATION * (1.0 - (s - target_s).abs())
+                + WEIGHT_LIGHTNESS * (1.0 - (l - target_l).abs())
+                + WEIGHT_POPULATION * (swatch.population as f64 / max_population as f64);
+            Some((i, score))
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(i, _)| i)
+}
+
+fn distance_sq(a: [u8; 3], b: [u8; 3]) -> u32 {
+    a.iter()
+        .zip(b)
+        .map(|(&x, y)| (x as i32 - y as i32).pow(2) as u32)
+        .sum()
+}
+
+/// Hue in degrees, saturation and lightness in `[0, 1]`.
+pub(crate) fn rgb_to_hsl([r, g, b]: [u8; 3]) -> (f64, f64, f64) {
+    let (r, g, b) = (r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+    let d = max - min;
+    if d == 0.0 {
+        return (0.0, 0.0, l);
+    }
+    let s = d / (1.0 - (2.0 * l - 1.0).abs());
+    let h = if max == r {
+        60.0 * ((g - b) / d).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / d + 2.0)
+    } else {
+        60.0 * ((r - g) / d + 4.0)
+    };
+    (h, s, l)
+}
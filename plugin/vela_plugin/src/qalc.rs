@@ -0,0 +1,159 @@
+use std::sync::Mutex;
+
+use crate::exchange_rates::Snapshot;
+
+pub(crate) use ffi::{Evaluation, Format, Message, Operation, Options, Severity};
+
+#[cxx::bridge(namespace = "Vela::qalc")]
+mod ffi {
+    /// How non-integer results are printed.
+    enum Format {
+        /// Decimal, rounded to `precision` significant digits.
+        Decimal,
+        /// Exact fractions where possible, e.g. `1/3`.
+        Fraction,
+        /// Always in scientific notation.
+        Scientific,
+    }
+
+    struct Options {
+        /// Significant digits for approximate results.
+        precision: i32,
+        format: Format,
+        /// Treat integer results as machine words: wrap them to `word_size`
+        /// bits (0 for unbounded), two's complement if `is_signed`, and
+        /// print them in every base.
+        programmer: bool,
+        word_size: i32,
+        is_signed: bool,
+    }
+
+    /// Symbolic manipulation done by `symbolic`.
+    enum Operation {
+        Simplify,
+        Solve,
+        Differentiate,
+        Integrate,
+    }
+
+    enum Severity {
+        Info,
+        Warning,
+        Error,
+    }
+
+    struct Message {
+        severity: Severity,
+        text: String,
+    }
+
+    /// Printed result, the expression as libqalculate understood it, and
+    /// any messages raised while parsing or calculating.
+    struct Evaluation {
+        result: String,
+        parsed: String,
+        messages: Vec<Message>,
+        /// In programmer mode, an integer result in decimal, hexadecimal,
+        /// octal and binary; otherwise empty.
+        bases: Vec<String>,
+        /// When the result depends on exchange rates, the time of their
+        /// snapshot in seconds since the epoch; otherwise 0.
+        rates_time: i64,
+        /// For symbolic operations, `result` in ASCII without unicode signs;
+        /// otherwise empty.
+        plain: String,
+    }
+
+    /// Units of `currency` one euro buys.
+    struct Rate {
+        currency: String,
+        per_euro: f64,
+    }
+
+    unsafe extern "C++" {
+        include!("vela_plugin/cpp/qalc.h");
+
+        fn evaluate(expr: &str, options: &Options, timeout_ms: i32) -> Evaluation;
+        fn define(name: &str, expr: &str, timeout_ms: i32) -> Evaluation;
+        fn undefine(name: &str);
+        fn symbolic(
+            expr: &str,
+            operation: Operation,
+            variable: &str,
+            timeout_ms: i32,
+        ) -> Evaluation;
+        #[cxx_name = "setExchangeRates"]
+        fn set_exchange_rates(rates: &[Rate], timestamp: i64) -> usize;
+    }
+}
+
+/// libqalculate keeps a single global calculator that is not thread safe.
+static CALCULATOR: Mutex<()> = Mutex::new(());
+
+/// Evaluate `expr`, giving up after `timeout_ms`. Integers and rationals
+/// are exact at any size; only irrational results are rounded.
+pub(crate) fn evaluate(expr: &str, options: &Options, timeout_ms: i32) -> Evaluation {
+    let _guard = CALCULATOR.lock().unwrap_or_else(|e| e.into_inner());
+    ffi::evaluate(expr, options, timeout_ms)
+}
+
+/// Bind the value of `expr` to the user variable `name`. On success the
+/// result holds the value printed exactly, for persisting.
+pub(crate) fn define(name: &str, expr: &str, timeout_ms: i32) -> Evaluation {
+    let _guard = CALCULATOR.lock().unwrap_or_else(|e| e.into_inner());
+    ffi::define(name, expr, timeout_ms)
+}
+
+/// Forget the user variable `name`, as defined by `define`.
+pub(crate) fn undefine(name: &str) {
+    let _guard = CALCULATOR.lock().unwrap_or_else(|e| e.into_inner());
+    ffi::undefine(name)
+}
+
+/// Apply `operation` to `expr`, treating undefined names as free variables.
+/// An empty `variable` picks the first one in `expr`, or `x`.
+pub(crate) fn symbolic(
+    expr: &str,
+    operation: Operation,
+    variable: &str,
+    timeout_ms: i32,
+) -> Evaluation {
+    let _guard = CALCULATOR.lock().unwrap_or_else(|e| e.into_inner());
+    ffi::symbolic(expr, operation, variable, timeout_ms)
+}
+
+/// Use the rates in `snapshot` for currency conversions from now on. Only
+/// ISO 4217 style codes are taken. Returns how many rates were applied, 0 if
+/// the snapshot cannot be expressed in euros.
+pub(crate) fn set_exchange_rates(snapshot: &Snapshot) -> usize {
+    let Some(rates) = snapshot.rebased("EUR") else {
+        return 0;
+    };
+    let rates: Vec<ffi::Rate> = rates
+        .into_iter()
+        .filter(|(code, _)| code.len() == 3 && code.bytes().all(|b| b.is_ascii_uppercase()))
+        .map(|(currency, per_euro)| ffi::Rate { currency, per_euro })
+        .collect();
+    let _guard = CALCULATOR.lock().unwrap_or_else(|e| e.into_inner());
+    ffi::set_exchange_rates(&rates, snapshot.timestamp)
+}
+
+impl Evaluation {
+    /// Errors and warnings as "error: ..." / "warning: ..." lines, if any.
+    pub fn problems(&self) -> Option<String> {
+        let lines: Vec<String> = self
+            .messages
+            .iter()
+            .filter_map(|message| match message.severity {
+                Severity::Error => Some(format!("error: {}", message.text)),
+                Severity::Warning => Some(format!("warning: {}", message.text)),
+                _ => None,
+            })
+            .collect();
+        (!lines.is_empty()).then(|| lines.join("\n"))
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.messages.iter().any(|m| m.severity == Severity::Error)
+    }
+}
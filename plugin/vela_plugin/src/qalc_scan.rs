@@ -0,0 +1,241 @@
+/// Symbolic binary operators, longest first so `<<` wins over `<`.
+const OPERATORS: &[&str] = &[
+    "<<", ">>", "**", "!=", "==", "<=", ">=", ":=", "+", "-", "*", "/", "^", "&", "|", "=", ",",
+    "<", ">", "×", "÷", "−", "·", "∙",
+];
+
+/// Word operators; only matched as whole words.
+const WORD_OPERATORS: &[&str] = &["to", "mod", "rem", "xor", "and", "or", "per", "of"];
+
+/// Radix prefixes with no digits yet.
+const RADIX_PREFIXES: &[&str] = &["0x", "0b", "0o", "0X", "0B", "0O"];
+
+/// What a quick lexical pass makes of an expression being typed.
+pub(crate) struct Scan {
+    /// The expression cannot be complete as written, e.g. `2 * (3 +`.
+    pub incomplete: bool,
+    /// Byte range of the offending part, if one could be pinned down.
+    pub span: Option<(usize, usize)>,
+    /// For incomplete input, the longest sensible prefix with brackets and
+    /// quotes closed, to preview a partial result. Otherwise the input.
+    pub repaired: String,
+}
+
+/// Look for unbalanced brackets and quotes, dangling operators and radix
+/// prefixes without digits. Purely lexical: anything it lets through may
+/// still fail to evaluate.
+pub(crate) fn scan(expr: &str) -> Scan {
+    let open = match open_brackets(expr) {
+        Ok(open) => open,
+        Err(span) => {
+            return Scan {
+                incomplete: false,
+                span: Some(span),
+                repaired: expr.to_string(),
+            }
+        }
+    };
+
+    let mut span = None;
+    let mut repaired = expr.trim_end().to_string();
+    if let Some(&(start, _)) = open.last() {
+        span = Some((start, expr.len() - start));
+    }
+    // Peel dangling operators and empty brackets off the end until what is
+    // left could be evaluated, remembering the first thing removed.
+    loop {
+        let trimmed = repaired.trim_end();
+        let Some(tail) = dangling_tail(trimmed) else {
+            break;
+        };
+        let start = trimmed.len() - tail;
+        span.get_or_insert((start, tail));
+        repaired.truncate(start);
+    }
+    repaired.truncate(repaired.trim_end().len());
+
+    let incomplete = span.is_some();
+    if incomplete {
+        // Re-derive what is still open after peeling.
+        if let Ok(open) = open_brackets(&repaired) {
+            for &(_, c) in open.iter().rev() {
+                repaired.push(closing(c));
+            }
+        }
+    }
+    Scan {
+        incomplete,
+        span,
+        repaired,
+    }
+}
+
+/// Byte range of `needle` in `expr`, for pointing at a token named in an
+/// error message.
+pub(crate) fn find_token(expr: &str, needle: &str) -> Option<(usize, usize)> {
+    if needle.is_empty() {
+        return None;
+    }
+    expr.find(needle).map(|start| (start, needle.len()))
+}
+
+/// Convert a byte offset in `s` to UTF-16 code units, as QML indexes text.
+pub(crate) fn utf16_offset(s: &str, byte: usize) -> usize {
+    s[..byte.min(s.len())].encode_utf16().count()
+}
+
+/// Unclosed brackets and quotes with their byte offsets, innermost last, or
+/// the span of a closing bracket that matches nothing.
+fn open_brackets(expr: &str) -> Result<Vec<(usize, char)>, (usize, usize)> {
+    let mut open: Vec<(usize, char)> = Vec::new();
+    for (i, c) in expr.char_indices() {
+        let in_quote = matches!(open.last(), Some((_, '"' | '\'')));
+        match c {
+            '"' | '\'' if in_quote && open.last().is_some_and(|&(_, q)| q == c) => {
+                open.pop();
+            }
+            _ if in_quote => {}
+            '"' | '\'' | '(' | '[' | '{' => open.push((i, c)),
+            ')' | ']' | '}' => match open.last() {
+                Some(&(_, o)) if closing(o) == c => {
+                    open.pop();
+                }
+                _ => return Err((i, c.len_utf8())),
+            },
+            _ => {}
+        }
+    }
+    Ok(open)
+}
+
+fn closing(open: char) -> char {
+    match open {
+        '(' => ')',
+        '[' => ']',
+        '{' => '}',
+        quote => quote,
+    }
+}
+
+/// Length in bytes of a trailing operator, empty bracket pair opener or
+/// bare radix prefix at the end of `s`.
+fn dangling_tail(s: &str) -> Option<usize> {
+    if s.ends_with(['(', '[', '{']) {
+        return Some(1);
+    }
+    if let Some(op) = OPERATORS.iter().find(|op| s.ends_with(*op)) {
+        return Some(op.len());
+    }
+    let word_start = |len: usize| {
+        let before = &s[..s.len() - len];
+        before.is_empty() || before.ends_with(|c: char| !c.is_alphanumeric() && c != '_')
+    };
+    if let Some(word) = WORD_OPERATORS
+        .iter()
+        .find(|w| s.ends_with(*w) && word_start(w.len()) && s.len() > w.len())
+    {
+        return Some(word.len());
+    }
+    RADIX_PREFIXES
+        .iter()
+        .find(|p| s.ends_with(*p) && word_start(p.len()))
+        .map(|p| p.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn complete_input_passes_through() {
+        for expr in ["1 + 2", "sin(pi / 2)", "0xFF to bin", "\"a(\" + 1", "5 mod 3"] {
+            let result = scan(expr);
+            assert!(!result.incomplete, "{expr}");
+            assert_eq!(result.span, None, "{expr}");
+            assert_eq!(result.repaired, expr);
+        }
+    }
+
+    #[test]
+    fn unclosed_brackets_are_closed() {
+        let result = scan("2 * (3 + [4");
+        assert!(result.incomplete);
+        assert_eq!(result.span, Some((9, 2)));
+        assert_eq!(result.repaired, "2 * (3 + [4])");
+    }
+
+    #[test]
+    fn dangling_operators_are_peeled() {
+        let result = scan("2 * (3 +");
+        assert!(result.incomplete);
+        assert_eq!(result.span, Some((4, 4)));
+        assert_eq!(result.repaired, "2 * (3)");
+
+        let result = scan("1 + 2 *  ");
+        assert!(result.incomplete);
+        assert_eq!(result.span, Some((6, 1)));
+        assert_eq!(result.repaired, "1 + 2");
+
+        // An empty bracket and the operator before it both go.
+        let result = scan("4 ^ (");
+        assert_eq!(result.span, Some((4, 1)));
+        assert_eq!(result.repaired, "4");
+    }
+
+    #[test]
+    fn unmatched_closing_bracket_is_an_error() {
+        let result = scan("(1 + 2]");
+        assert!(!result.incomplete);
+        assert_eq!(result.span, Some((6, 1)));
+        assert_eq!(result.repaired, "(1 + 2]");
+    }
+
+    #[test]
+    fn quotes_hide_brackets() {
+        let result = scan("\"(\" + 'x");
+        assert!(result.incomplete);
+        assert_eq!(result.span, Some((6, 2)));
+        assert_eq!(result.repaired, "\"(\" + 'x'");
+    }
+
+    #[test]
+    fn dangling_tail_recognises_operators() {
+        assert_eq!(dangling_tail("1 +"), Some(1));
+        assert_eq!(dangling_tail("1 <<"), Some(2));
+        assert_eq!(dangling_tail("1 ×"), Some('×'.len_utf8()));
+        assert_eq!(dangling_tail("f("), Some(1));
+        assert_eq!(dangling_tail("1"), None);
+        assert_eq!(dangling_tail("x!"), None);
+    }
+
+    #[test]
+    fn dangling_tail_matches_whole_words_only() {
+        assert_eq!(dangling_tail("5 mod"), Some(3));
+        assert_eq!(dangling_tail("5 m to"), Some(2));
+        // Part of a longer name, or the whole input.
+        assert_eq!(dangling_tail("photo"), None);
+        assert_eq!(dangling_tail("random"), None);
+        assert_eq!(dangling_tail("to"), None);
+    }
+
+    #[test]
+    fn dangling_tail_finds_bare_radix_prefixes() {
+        assert_eq!(dangling_tail("0x"), Some(2));
+        assert_eq!(dangling_tail("1 + 0b"), Some(2));
+        assert_eq!(dangling_tail("10x"), None);
+        assert_eq!(dangling_tail("0x1F"), None);
+    }
+
+    #[test]
+    fn utf16_offset_counts_code_units() {
+        let s = "π × 2 + 😀 + 1";
+        assert_eq!(utf16_offset(s, 0), 0);
+        assert_eq!(utf16_offset(s, 'π'.len_utf8()), 1);
+        let emoji = s.find('😀').unwrap();
+        assert_eq!(utf16_offset(s, emoji), 8);
+        // Astral characters take two units.
+        assert_eq!(utf16_offset(s, emoji + '😀'.len_utf8()), 10);
+        assert_eq!(utf16_offset(s, s.len()), s.encode_utf16().count());
+        assert_eq!(utf16_offset(s, s.len() + 5), s.encode_utf16().count());
+    }
+}
@@ -1,36 +1,727 @@
-use cxx_qt::QObject;
-use evalexpr::{eval, Value};
+use cxx_qt::{CxxQtThread, QEnum, QObject, Threading};
+use qml6::QJSValue;
+use qt6_core::{QString, QVariant, QVariantList, QVariantMap};
 
-#[derive(QObject, Default)]
-pub struct Qalculator;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use std::thread;
+
+use crate::exchange_rates::{self, CurlFetcher, RateFetcher, Snapshot};
+use crate::file_ops;
+use crate::qalc::{self, Format, Operation, Options, Severity};
+use crate::qalc_scan;
+
+/// Evaluation budget; the launcher evaluates on every keystroke.
+const TIMEOUT_MS: i32 = 500;
+
+/// Budget for symbolic operations, which are asked for explicitly and may
+/// need longer than a keystroke preview.
+const SYMBOLIC_TIMEOUT_MS: i32 = 2000;
+
+/// Significant digits used until `precision` is set.
+const DEFAULT_PRECISION: i32 = 10;
+
+/// History entries kept, oldest dropped first.
+const MAX_HISTORY: usize = 100;
+
+/// Word size used until `wordSize` is set.
+const DEFAULT_WORD_SIZE: i32 = 64;
+
+/// Keys of the `evalBases` map, in the order libqalculate returns them.
+const BASE_NAMES: [&str; 4] = ["dec", "hex", "oct", "bin"];
+
+/// Variable holding the last committed result.
+const ANS: &str = "ans";
+
+/// How `eval` prints non-integer results.
+#[derive(QEnum, Clone, Copy, Default, PartialEq, Eq)]
+#[qenum(cpp_name = "OutputFormat")]
+pub enum OutputFormat {
+    /// `0.3333333333`
+    #[default]
+    Decimal,
+    /// `1/3`
+    Fraction,
+    /// `3.333333333E-1`
+    Scientific,
+}
+
+#[derive(Clone, PartialEq)]
+struct HistoryEntry {
+    expression: String,
+    result: String,
+}
+
+#[derive(QObject)]
+pub struct Qalculator {
+    /// Significant digits for results that cannot be shown exactly.
+    #[qproperty]
+    precision: i32,
+
+    #[qproperty(cpp_name = "outputFormat")]
+    output_format: OutputFormat,
+
+    /// Treat integer results as machine words of `wordSize` bits (0 for
+    /// unbounded), signed two's complement if `signedWords`. `1 << 40` is
+    /// then 0 with 32-bit words and `0xFF` is -1 as a signed byte.
+    #[qproperty(cpp_name = "programmerMode")]
+    programmer_mode: bool,
+
+    #[qproperty(cpp_name = "wordSize")]
+    word_size: i32,
+
+    #[qproperty(cpp_name = "signedWords")]
+    signed_words: bool,
+
+    /// File the session (variables, `ans` and history) is saved to. Empty
+    /// keeps the session in memory only.
+    #[qproperty(cpp_name = "historyPath")]
+    history_path: QString,
+
+    /// Committed calculations, newest first, as `{ expression, result }`.
+    #[qproperty(read, notify = "historyChanged")]
+    history: QVariantList,
+
+    /// Exchange rate cache, ECB XML or JSON (see `exchange_rates`). Currency
+    /// conversions always use this file, so they work offline; it is
+    /// refreshed from `ratesUrl` when older than a day. Empty leaves
+    /// libqalculate's own rates in place.
+    #[qproperty(cpp_name = "ratesPath")]
+    rates_path: QString,
+
+    /// Where `refreshRates` downloads rates from. Empty never goes online.
+    #[qproperty(cpp_name = "ratesUrl")]
+    rates_url: QString,
+
+    /// Time of the loaded rate snapshot in milliseconds since the epoch, or
+    /// 0 if none is loaded.
+    #[qproperty(read, cpp_name = "ratesTimestamp", notify = "ratesChanged")]
+    rates_timestamp: f64,
+
+    entries: Vec<HistoryEntry>,
+    /// User variables and `ans`, as name and exact value.
+    variables: Vec<(String, String)>,
+    refreshing: bool,
+    /// Latest request numbers of the asynchronous `evaluate` and
+    /// `evalBases`, so superseded requests can be dropped.
+    latest_evaluation: Arc<AtomicU64>,
+    latest_bases: Arc<AtomicU64>,
+    latest_symbolic: Arc<AtomicU64>,
+}
+
+impl Default for Qalculator {
+    fn default() -> Self {
+        Self {
+            precision: DEFAULT_PRECISION,
+            output_format: OutputFormat::Decimal,
+            programmer_mode: false,
+            word_size: DEFAULT_WORD_SIZE,
+            signed_words: true,
+            history_path: QString::default(),
+            history: QVariantList::default(),
+            rates_path: QString::default(),
+            rates_url: QString::from(exchange_rates::DEFAULT_URL),
+            rates_timestamp: 0.0,
+            entries: Vec::new(),
+            variables: Vec::new(),
+            refreshing: false,
+            latest_evaluation: Arc::new(AtomicU64::new(0)),
+            latest_bases: Arc::new(AtomicU64::new(0)),
+            latest_symbolic: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
 
 impl Qalculator {
+    /// `eval` with `print_expr` on, as the launcher preview uses it.
+    #[qinvokable(cpp_name = "eval")]
+    pub fn eval_default(&self, expr: &str) -> String {
+        self.eval(expr, true)
+    }
+
     /// Evaluate mathmatical expression. If `print_expr` is true,
     /// returns "expr = result", otherwise returns the result alone.
+    /// Backed by libqalculate, so units (`5 km to mi`), bases (`0x1F + 3`),
+    /// exact integers (`2^64`), functions, constants, percentages, dates
+    /// (`now + 3 days`) and temperatures (`20 °C to °F`) all work. Errors and
+    /// warnings come back as "error: ..." and "warning: ...".
+    ///
+    /// Arithmetic is exact on big integers and rationals, so `2^100` and
+    /// `0.1 + 0.2` print exactly; `precision` and `outputFormat` decide how
+    /// the remaining approximate results look.
+    ///
+    /// Session variables and `ans` can be used, but nothing is changed: an
+    /// assignment such as `x = 5` only previews its value until `commit`.
     #[qinvokable(cpp_name = "eval")]
     pub fn eval(&self, expr: &str, print_expr: bool) -> String {
         let trimmed = expr.trim();
         if trimmed.is_empty() {
             return String::new();
         }
-        match eval(trimmed) {
-            Ok(value) => {
-                let result = match value {
-                    Value::Float(f) => f.to_string(),
-                    Value::Int(i) => i.to_string(),
-                    Value::Boolean(b) => b.to_string(),
-                    Value::String(s) => s,
-                    _ => value.to_string(),
+        let (name, rhs) = match parse_assignment(trimmed) {
+            Some((name, rhs)) => (Some(name), rhs),
+            None => (None, trimmed),
+        };
+        let evaluation = qalc::evaluate(rhs, &self.options(), TIMEOUT_MS);
+        if let Some(problems) = evaluation.problems() {
+            return problems;
+        }
+        match (print_expr, name) {
+            (true, Some(name)) => format!("{name} = {}", evaluation.result),
+            (true, None) => format!("{} = {}", evaluation.parsed, evaluation.result),
+            (false, _) => evaluation.result,
+        }
+    }
+
+    /// Structured `eval` for as-you-type previews. Returns
+    /// `{ valid, value, expression, incomplete, error, errorStart, errorLength,
+    /// warning, ratesTimestamp }`: `value` and `expression` are the result and
+    /// the normalized input, `incomplete` flags input that cannot be finished
+    /// as written (`2 * (3 +`), and `errorStart`/`errorLength` locate the
+    /// problem in `expr` in UTF-16 units, or are -1 and 0. Incomplete input
+    /// still gets a partial `value` from its longest complete prefix when one
+    /// exists. `ratesTimestamp` is the snapshot time, in milliseconds since
+    /// the epoch, of the exchange rates a currency conversion used, else 0.
+    #[qinvokable]
+    pub fn evaluate(&self, expr: &str) -> QVariantMap {
+        evaluation_map(expr, &self.options())
+    }
+
+    /// `evaluate` on a worker thread, so the UI stays responsive while
+    /// libqalculate uses up its budget. `callback` receives the map; calls
+    /// superseded by a newer one before they got to run are dropped.
+    #[qinvokable(cpp_name = "evaluate")]
+    pub fn evaluate_async(&self, expr: &str, callback: QJSValue) {
+        let expr = expr.to_string();
+        let options = self.options();
+        run_latest(self.qt_thread(), &self.latest_evaluation, callback, move || {
+            evaluation_map(&expr, &options)
+        });
+    }
+
+    /// Make a calculation part of the session on a worker thread: `x = 5`
+    /// and `x := 5` define `x`, the value becomes `ans`, and the calculation
+    /// is added to `history`. `result` is the `value` that `evaluate` gave
+    /// for `expr` and is used as is, so `ans` matches what was shown and
+    /// copied even for `rand()`, `now` or currencies. `callback` receives
+    /// `(result, null)`, or `(null, error)` if a variable cannot be defined.
+    #[qinvokable]
+    pub fn commit(&self, expr: &str, result: &str, callback: QJSValue) {
+        let expression = expr.trim().to_string();
+        let result = result.trim().to_string();
+        let qt_thread = self.qt_thread();
+        thread::spawn(move || {
+            let defined = define_session(&expression, &result);
+            let _ = qt_thread.queue(move |obj: &mut Qalculator| {
+                let outcome = defined.map(|defined| obj.record(expression, &result, defined));
+                if !callback.is_callable() {
+                    return;
+                }
+                match outcome {
+                    Ok(()) => callback.call(&[
+                        QJSValue::from(&QString::from(result.as_str())),
+                        QJSValue::null(),
+                    ]),
+                    Err(e) => callback.call(&[QJSValue::null(), QJSValue::from(&QString::from(e))]),
                 };
-                if print_expr {
-                    format!("{} = {}", trimmed, result)
-                } else {
-                    result
+            });
+        });
+    }
+
+    /// The integer result of `expr` in every base, as `{ dec, hex, oct, bin }`
+    /// (`0x`, `0o` and `0b` prefixed), wrapped to the word size. Empty if the
+    /// result is not an integer or the expression has errors.
+    #[qinvokable(cpp_name = "evalBases")]
+    pub fn eval_bases(&self, expr: &str) -> QVariantMap {
+        bases_map(expr, self.options())
+    }
+
+    /// `evalBases` on a worker thread, like the asynchronous `evaluate`.
+    #[qinvokable(cpp_name = "evalBases")]
+    pub fn eval_bases_async(&self, expr: &str, callback: QJSValue) {
+        let expr = expr.to_string();
+        let options = self.options();
+        run_latest(self.qt_thread(), &self.latest_bases, callback, move || {
+            bases_map(&expr, options)
+        });
+    }
+
+    /// Simplify `expr`, which may contain free variables: `(x^2-1)/(x-1)`
+    /// gives `x + 1`. Returns `{ valid, pretty, plain, expression, error }`,
+    /// where `pretty` uses unicode signs (`x²`, `−`, `×`) and `plain` is the
+    /// same in ASCII for copying into other programs.
+    #[qinvokable]
+    pub fn simplify(&self, expr: &str) -> QVariantMap {
+        symbolic_map(expr, Operation::Simplify, "")
+    }
+
+    /// Solve the equation `expr` for `variable`, e.g. `2x + 3 = 11` gives
+    /// `x = 4`; several solutions are joined with "or". An empty `variable`
+    /// solves for the first unknown. Returns the same map as `simplify`.
+    #[qinvokable]
+    pub fn solve(&self, expr: &str, variable: &str) -> QVariantMap {
+        symbolic_map(expr, Operation::Solve, variable)
+    }
+
+    /// Derivative of `expr` with respect to `variable` (the first unknown if
+    /// empty). Returns the same map as `simplify`.
+    #[qinvokable]
+    pub fn differentiate(&self, expr: &str, variable: &str) -> QVariantMap {
+        symbolic_map(expr, Operation::Differentiate, variable)
+    }
+
+    /// Indefinite integral of `expr` with respect to `variable` (the first
+    /// unknown if empty), with `+ C`. Returns the same map as `simplify`.
+    #[qinvokable]
+    pub fn integrate(&self, expr: &str, variable: &str) -> QVariantMap {
+        symbolic_map(expr, Operation::Integrate, variable)
+    }
+
+    /// `simplify` on a worker thread; `callback` receives the map. Like the
+    /// other asynchronous symbolic operations, calls superseded by a newer
+    /// one before they got to run are dropped.
+    #[qinvokable(cpp_name = "simplify")]
+    pub fn simplify_async(&self, expr: &str, callback: QJSValue) {
+        self.symbolic_async(expr, Operation::Simplify, "", callback);
+    }
+
+    /// `solve` on a worker thread.
+    #[qinvokable(cpp_name = "solve")]
+    pub fn solve_async(&self, expr: &str, variable: &str, callback: QJSValue) {
+        self.symbolic_async(expr, Operation::Solve, variable, callback);
+    }
+
+    /// `differentiate` on a worker thread.
+    #[qinvokable(cpp_name = "differentiate")]
+    pub fn differentiate_async(&self, expr: &str, variable: &str, callback: QJSValue) {
+        self.symbolic_async(expr, Operation::Differentiate, variable, callback);
+    }
+
+    /// `integrate` on a worker thread.
+    #[qinvokable(cpp_name = "integrate")]
+    pub fn integrate_async(&self, expr: &str, variable: &str, callback: QJSValue) {
+        self.symbolic_async(expr, Operation::Integrate, variable, callback);
+    }
+
+    /// Forget all history entries. Variables and `ans` are kept.
+    #[qinvokable(cpp_name = "clearHistory")]
+    pub fn clear_history(&mut self) {
+        if self.entries.is_empty() {
+            return;
+        }
+        self.entries.clear();
+        self.history_updated();
+        self.save();
+    }
+
+    /// Download fresh exchange rates from `ratesUrl` on a worker thread,
+    /// replace the cache at `ratesPath` and start using them. On failure the
+    /// previous snapshot stays in use.
+    #[qinvokable(cpp_name = "refreshRates")]
+    pub fn refresh_rates(&mut self) {
+        let path = self.rates_path.to_string();
+        let Some(fetcher) = self.fetcher() else {
+            return;
+        };
+        if path.is_empty() || self.refreshing {
+            return;
+        }
+        self.refreshing = true;
+        let path = PathBuf::from(path);
+        let qt_thread = self.qt_thread();
+        thread::spawn(move || {
+            let result = exchange_rates::refresh(fetcher.as_ref(), &path);
+            let _ = qt_thread.queue(move |qalculator: &mut Qalculator| {
+                qalculator.refreshing = false;
+                match result {
+                    // The path may have changed while fetching.
+                    Ok(snapshot) if qalculator.rates_path.to_string() == path.to_string_lossy() => {
+                        qalculator.apply_rates(&snapshot)
+                    }
+                    Ok(_) => {}
+                    Err(e) => eprintln!("Qalculator: unable to refresh exchange rates: {e}"),
                 }
+            });
+        });
+    }
+
+    #[qproperty(cpp_name = "ratesPath")]
+    pub fn set_rates_path(&mut self, path: &QString) {
+        if &self.rates_path == path {
+            return;
+        }
+        self.rates_path = path.clone();
+        let cache = PathBuf::from(path.to_string());
+        let stale = match Snapshot::load(&cache) {
+            Ok(snapshot) => {
+                self.apply_rates(&snapshot);
+                exchange_rates::is_stale(&cache)
             }
-            Err(e) => format!("error: {}", e),
+            Err(_) => true,
+        };
+        if stale && !path.is_empty() {
+            // Queued so a `ratesUrl` set in the same binding pass is honoured.
+            let _ = self.qt_thread().queue(|qalculator: &mut Qalculator| {
+                qalculator.refresh_rates();
+            });
+        }
+        self.ratesPathChanged();
+    }
+
+    #[qproperty(cpp_name = "historyPath")]
+    pub fn set_history_path(&mut self, path: &QString) {
+        if &self.history_path == path {
+            return;
         }
+        self.history_path = path.clone();
+        self.load();
+        self.historyPathChanged();
     }
+
+    #[cxx_qt::qsignal]
+    fn historyChanged(&self);
+
+    #[cxx_qt::qsignal]
+    fn ratesChanged(&self);
+
+    fn symbolic_async(&self, expr: &str, operation: Operation, variable: &str, callback: QJSValue) {
+        let expr = expr.to_string();
+        let variable = variable.to_string();
+        run_latest(self.qt_thread(), &self.latest_symbolic, callback, move || {
+            symbolic_map(&expr, operation, &variable)
+        });
+    }
+
+    /// Source for `refreshRates`; other `RateFetcher`s can be swapped in here.
+    fn fetcher(&self) -> Option<Box<dyn RateFetcher>> {
+        let url = self.rates_url.to_string();
+        (!url.is_empty()).then(|| Box::new(CurlFetcher { url }) as Box<dyn RateFetcher>)
+    }
+
+    fn apply_rates(&mut self, snapshot: &Snapshot) {
+        if qalc::set_exchange_rates(snapshot) == 0 {
+            eprintln!("Qalculator: no usable exchange rates in {}", self.rates_path);
+            return;
+        }
+        self.rates_timestamp = snapshot.timestamp as f64 * 1000.0;
+        self.ratesChanged();
+    }
+
+    fn options(&self) -> Options {
+        Options {
+            precision: self.precision.clamp(1, 1000),
+            format: match self.output_format {
+                OutputFormat::Decimal => Format::Decimal,
+                OutputFormat::Fraction => Format::Fraction,
+                OutputFormat::Scientific => Format::Scientific,
+            },
+            programmer: self.programmer_mode,
+            word_size: self.word_size.clamp(0, 4096),
+            is_signed: self.signed_words,
+        }
+    }
+
+    /// Define `name` in libqalculate and remember its exact value.
+    fn define(&mut self, name: &str, expr: &str) -> Result<(), String> {
+        let value = define_variable(name, expr)?;
+        self.set_variable(name.to_string(), value);
+        Ok(())
+    }
+
+    fn set_variable(&mut self, name: String, value: String) {
+        self.variables.retain(|(n, _)| *n != name);
+        self.variables.push((name, value));
+    }
+
+    /// Add a committed calculation to the session, with the variables
+    /// `define_session` defined for it.
+    fn record(&mut self, expression: String, result: &str, defined: Vec<(String, String)>) {
+        for (name, value) in defined {
+            self.set_variable(name, value);
+        }
+        self.entries.retain(|e| e.expression != expression);
+        self.entries.insert(
+            0,
+            HistoryEntry {
+                expression,
+                result: result.to_string(),
+            },
+        );
+        self.entries.truncate(MAX_HISTORY);
+        self.history_updated();
+        self.save();
+    }
+
+    fn history_updated(&mut self) {
+        let mut list = QVariantList::default();
+        for entry in &self.entries {
+            let mut map = QVariantMap::default();
+            map.insert("expression", QVariant::from(&QString::from(entry.expression.as_str())));
+            map.insert("result", QVariant::from(&QString::from(entry.result.as_str())));
+            list.append(QVariant::from(&map));
+        }
+        self.history = list;
+        self.historyChanged();
+    }
+
+    /// Restore variables and history from `history_path`. The file is
+    /// tab-separated lines of `var <name> <value>` and
+    /// `entry <expression> <result>`, newest entry first.
+    fn load(&mut self) {
+        self.entries.clear();
+        // Variables of the previous session must not leak into this one.
+        for (name, _) in std::mem::take(&mut self.variables) {
+            qalc::undefine(&name);
+        }
+        let path = self.history_path.to_string();
+        let contents = if path.is_empty() {
+            String::new()
+        } else {
+            fs::read_to_string(&path).unwrap_or_default()
+        };
+        for line in contents.lines() {
+            let mut fields = line.splitn(3, '\t');
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some("var"), Some(name), Some(value)) => {
+                    if let Err(e) = self.define(name, value) {
+                        eprintln!("Qalculator: unable to restore {name}: {e}");
+                    }
+                }
+                (Some("entry"), Some(expression), Some(result)) => {
+                    self.entries.push(HistoryEntry {
+                        expression: expression.to_string(),
+                        result: result.to_string(),
+                    })
+                }
+                _ => {}
+            }
+        }
+        self.entries.truncate(MAX_HISTORY);
+        self.history_updated();
+    }
+
+    fn save(&self) {
+        let path = self.history_path.to_string();
+        if path.is_empty() {
+            return;
+        }
+        let mut contents = String::new();
+        for (name, value) in &self.variables {
+            contents.push_str(&format!("var\t{name}\t{}\n", single_line(value)));
+        }
+        for entry in &self.entries {
+            contents.push_str(&format!(
+                "entry\t{}\t{}\n",
+                single_line(&entry.expression),
+                single_line(&entry.result)
+            ));
+        }
+        let path = Path::new(&path);
+        if let Some(parent) = path.parent() {
+            let _ = file_ops::make_dirs(parent);
+        }
+        if let Err(e) = file_ops::write_atomic(path, contents.as_bytes()) {
+            eprintln!("Qalculator: unable to save session: {}", e.message);
+        }
+    }
+}
+
+/// Split `name = expr` or `name := expr` into its parts. Comparisons such
+/// as `x == 5` and anything whose left side is not a plain identifier are
+/// left to libqalculate, which treats `=` as an equation.
+fn parse_assignment(expr: &str) -> Option<(&str, &str)> {
+    let (name, rhs) = expr.split_once(":=").or_else(|| {
+        let (name, rhs) = expr.split_once('=')?;
+        (!rhs.starts_with('=') && !name.ends_with(['<', '>', '!'])).then_some((name, rhs))
+    })?;
+    let (name, rhs) = (name.trim(), rhs.trim());
+    let mut chars = name.chars();
+    let is_identifier = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_');
+    (is_identifier && !rhs.is_empty() && name != ANS).then_some((name, rhs))
+}
+
+/// Bind `expr` to `name` in libqalculate and return the value printed
+/// exactly, for `variables`.
+fn define_variable(name: &str, expr: &str) -> Result<String, String> {
+    let evaluation = qalc::define(name, expr, TIMEOUT_MS);
+    if evaluation.is_error() {
+        return Err(evaluation.problems().unwrap_or_default());
+    }
+    Ok(evaluation.result)
+}
+
+/// Body of `commit` that may run on a worker thread: define the variable
+/// `expression` assigns and `ans` from the already evaluated `result`,
+/// returning the new `variables` entries.
+fn define_session(expression: &str, result: &str) -> Result<Vec<(String, String)>, String> {
+    if expression.is_empty() || result.is_empty() {
+        return Err("nothing to commit".to_string());
+    }
+    let mut defined = Vec::new();
+    if let Some((name, _)) = parse_assignment(expression) {
+        defined.push((name.to_string(), define_variable(name, result)?));
+    }
+    match define_variable(ANS, result) {
+        Ok(value) => defined.push((ANS.to_string(), value)),
+        Err(e) => eprintln!("Qalculator: unable to update {ANS}: {e}"),
+    }
+    Ok(defined)
+}
+
+/// Run `work` on a worker thread and pass its result to `callback` on the Qt
+/// thread. Each call takes a new number from `latest`; work that has been
+/// superseded by the time it would start or finish is dropped, so keystrokes
+/// arriving faster than evaluation neither queue up nor call back late.
+fn run_latest(
+    qt_thread: CxxQtThread<Qalculator>,
+    latest: &Arc<AtomicU64>,
+    callback: QJSValue,
+    work: impl FnOnce() -> QVariantMap + Send + 'static,
+) {
+    let request = latest.fetch_add(1, Ordering::AcqRel) + 1;
+    let latest = latest.clone();
+    let current = move || latest.load(Ordering::Acquire) == request;
+    thread::spawn(move || {
+        if !current() {
+            return;
+        }
+        let map = work();
+        let _ = qt_thread.queue(move |_: &mut Qalculator| {
+            if current() && callback.is_callable() {
+                callback.call(&[QJSValue::from(&QVariant::from(&map))]);
+            }
+        });
+    });
+}
+
+/// Body of `evaluate`, which may also run on a worker thread.
+fn evaluation_map(expr: &str, options: &Options) -> QVariantMap {
+    let mut map = QVariantMap::default();
+    let trimmed = expr.trim();
+    let (name, rhs) = match parse_assignment(trimmed) {
+        Some((name, rhs)) => (Some(name), rhs),
+        None => (None, trimmed),
+    };
+    // Spans below are relative to `rhs`, a slice of `expr`.
+    let offset = rhs.as_ptr() as usize - expr.as_ptr() as usize;
+
+    let scan = qalc_scan::scan(rhs);
+    let input = if scan.incomplete { scan.repaired.as_str() } else { rhs };
+    let evaluation = (!input.trim().is_empty())
+        .then(|| qalc::evaluate(input, options, TIMEOUT_MS));
+    let failed = evaluation.as_ref().is_none_or(|e| e.is_error());
+
+    let mut span = scan.span;
+    let mut error = String::new();
+    let mut warning = String::new();
+    if let Some(evaluation) = &evaluation {
+        for message in &evaluation.messages {
+            match message.severity {
+                Severity::Error if error.is_empty() => error = message.text.clone(),
+                Severity::Warning if warning.is_empty() => warning = message.text.clone(),
+                _ => {}
+            }
+        }
+    }
+    if span.is_none() && failed && !rhs.is_empty() {
+        // libqalculate quotes the name it tripped over, if any.
+        span = error
+            .split('"')
+            .nth(1)
+            .and_then(|token| qalc_scan::find_token(rhs, token))
+            .or(Some((0, rhs.len())));
+    }
+    if scan.incomplete && error.is_empty() {
+        error = "incomplete expression".to_string();
+    }
+
+    let rates_time = match (&evaluation, failed) {
+        (Some(e), false) => e.rates_time as f64 * 1000.0,
+        _ => 0.0,
+    };
+    let (value, expression) = match (&evaluation, failed) {
+        (Some(e), false) => (
+            e.result.clone(),
+            name.map_or_else(|| e.parsed.clone(), str::to_string),
+        ),
+        _ => (String::new(), String::new()),
+    };
+    let (error_start, error_length) = match span {
+        Some((start, len)) => {
+            let start = offset + start;
+            let begin = qalc_scan::utf16_offset(expr, start);
+            (begin as i32, (qalc_scan::utf16_offset(expr, start + len) - begin) as i32)
+        }
+        None => (-1, 0),
+    };
+
+    map.insert("valid", QVariant::from(&(!failed && !scan.incomplete && span.is_none())));
+    map.insert("value", QVariant::from(&QString::from(value.as_str())));
+    map.insert("expression", QVariant::from(&QString::from(expression.as_str())));
+    map.insert("incomplete", QVariant::from(&scan.incomplete));
+    map.insert("error", QVariant::from(&QString::from(error.as_str())));
+    map.insert("errorStart", QVariant::from(&error_start));
+    map.insert("errorLength", QVariant::from(&error_length));
+    map.insert("warning", QVariant::from(&QString::from(warning.as_str())));
+    map.insert("ratesTimestamp", QVariant::from(&rates_time));
+    map
+}
+
+/// Body of `evalBases`, which may also run on a worker thread.
+fn bases_map(expr: &str, options: Options) -> QVariantMap {
+    let trimmed = expr.trim();
+    let mut map = QVariantMap::default();
+    if trimmed.is_empty() {
+        return map;
+    }
+    let rhs = parse_assignment(trimmed).map_or(trimmed, |(_, rhs)| rhs);
+    let options = Options {
+        programmer: true,
+        ..options
+    };
+    let evaluation = qalc::evaluate(rhs, &options, TIMEOUT_MS);
+    if evaluation.is_error() {
+        return map;
+    }
+    for (name, value) in BASE_NAMES.iter().zip(&evaluation.bases) {
+        map.insert(name, QVariant::from(&QString::from(value.as_str())));
+    }
+    map
+}
+
+fn symbolic_map(expr: &str, operation: Operation, variable: &str) -> QVariantMap {
+    let mut map = QVariantMap::default();
+    let trimmed = expr.trim();
+    let evaluation = (!trimmed.is_empty())
+        .then(|| qalc::symbolic(trimmed, operation, variable.trim(), SYMBOLIC_TIMEOUT_MS));
+    let error = match &evaluation {
+        Some(e) => e
+            .messages
+            .iter()
+            .find(|m| m.severity == Severity::Error)
+            .map(|m| m.text.clone())
+            .unwrap_or_default(),
+        None => "empty expression".to_string(),
+    };
+    let (pretty, plain, expression) = match &evaluation {
+        Some(e) if error.is_empty() => (e.result.as_str(), e.plain.as_str(), e.parsed.as_str()),
+        _ => ("", "", ""),
+    };
+    map.insert("valid", QVariant::from(&error.is_empty()));
+    map.insert("pretty", QVariant::from(&QString::from(pretty)));
+    map.insert("plain", QVariant::from(&QString::from(plain)));
+    map.insert("expression", QVariant::from(&QString::from(expression)));
+    map.insert("error", QVariant::from(&QString::from(error.as_str())));
+    map
+}
+
+fn single_line(s: &str) -> String {
+    s.replace(['\t', '\n'], " ")
 }
 
 pub fn register() {
@@ -0,0 +1,429 @@
+use cxx_qt::{QObject, Threading};
+use image::{imageops::FilterType, DynamicImage};
+use material_colors::{
+    color::Argb,
+    dynamic_color::{DynamicColor, DynamicScheme, MaterialDynamicColors as Md},
+    hct::Hct,
+    quantize::{Quantizer, QuantizerCelebi},
+    scheme::variant::{
+        SchemeContent, SchemeExpressive, SchemeFidelity, SchemeFruitSalad, SchemeMonochrome,
+        SchemeNeutral, SchemeRainbow, SchemeTonalSpot, SchemeVibrant,
+    },
+    score::Score,
+};
+use qml6::QJSValue;
+use qt6_core::{QColor, QString, QVariant, QVariantMap};
+
+use std::path::Path;
+use std::thread;
+
+use crate::image_loader;
+
+/// Side length images are downscaled to before quantizing.
+const SAMPLE_SIZE: u32 = 128;
+
+/// Colors kept by the quantizer before scoring.
+const MAX_COLORS: usize = 128;
+
+/// Fallback seed when an image has no suitable source color (Google blue).
+const DEFAULT_SEED: u32 = 0xff4285f4;
+
+/// Scheme variants, named as in `M3Variants.qml` and the `vela scheme` CLI.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Variant {
+    TonalSpot,
+    Vibrant,
+    Expressive,
+    Fidelity,
+    Content,
+    FruitSalad,
+    Rainbow,
+    Neutral,
+    Monochrome,
+}
+
+impl Variant {
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "tonalspot" => Variant::TonalSpot,
+            "vibrant" => Variant::Vibrant,
+            "expressive" => Variant::Expressive,
+            "fidelity" => Variant::Fidelity,
+            "content" => Variant::Content,
+            "fruitsalad" => Variant::FruitSalad,
+            "rainbow" => Variant::Rainbow,
+            "neutral" => Variant::Neutral,
+            "monochrome" => Variant::Monochrome,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Variant::TonalSpot => "tonalspot",
+            Variant::Vibrant => "vibrant",
+            Variant::Expressive => "expressive",
+            Variant::Fidelity => "fidelity",
+            Variant::Content => "content",
+            Variant::FruitSalad => "fruitsalad",
+            Variant::Rainbow => "rainbow",
+            Variant::Neutral => "neutral",
+            Variant::Monochrome => "monochrome",
+        }
+    }
+
+    fn scheme(self, source: Hct, dark: bool, contrast: f64) -> DynamicScheme {
+        let contrast = Some(contrast);
+        match self {
+            Variant::TonalSpot => SchemeTonalSpot::new(source, dark, contrast).scheme,
+            Variant::Vibrant => SchemeVibrant::new(source, dark, contrast).scheme,
+            Variant::Expressive => SchemeExpressive::new(source, dark, contrast).scheme,
+            Variant::Fidelity => SchemeFidelity::new(source, dark, contrast).scheme,
+            Variant::Content => SchemeContent::new(source, dark, contrast).scheme,
+            Variant::FruitSalad => SchemeFruitSalad::new(source, dark, contrast).scheme,
+            Variant::Rainbow => SchemeRainbow::new(source, dark, contrast).scheme,
+            Variant::Neutral => SchemeNeutral::new(source, dark, contrast).scheme,
+            Variant::Monochrome => SchemeMonochrome::new(source, dark, contrast).scheme,
+        }
+    }
+}
+
+/// Every role in `Colors.qml`'s `M3Palette`, without the `m3` prefix.
+const ROLES: &[(&str, fn() -> DynamicColor)] = &[
+    ("primary_paletteKeyColor", Md::primary_palette_key_color),
+    ("secondary_paletteKeyColor", Md::secondary_palette_key_color),
+    ("tertiary_paletteKeyColor", Md::tertiary_palette_key_color),
+    ("neutral_paletteKeyColor", Md::neutral_palette_key_color),
+    ("neutral_variant_paletteKeyColor", Md::neutral_variant_palette_key_color),
+    ("background", Md::background),
+    ("onBackground", Md::on_background),
+    ("surface", Md::surface),
+    ("surfaceDim", Md::surface_dim),
+    ("surfaceBright", Md::surface_bright),
+    ("surfaceContainerLowest", Md::surface_container_lowest),
+    ("surfaceContainerLow", Md::surface_container_low),
+    ("surfaceContainer", Md::surface_container),
+    ("surfaceContainerHigh", Md::surface_container_high),
+    ("surfaceContainerHighest", Md::surface_container_highest),
+    ("onSurface", Md::on_surface),
+    ("surfaceVariant", Md::surface_variant),
+    ("onSurfaceVariant", Md::on_surface_variant),
+    ("inverseSurface", Md::inverse_surface),
+    ("inverseOnSurface", Md::inverse_on_surface),
+    ("outline", Md::outline),
+    ("outlineVariant", Md::outline_variant),
+    ("shadow", Md::shadow),
+    ("scrim", Md::scrim),
+    ("surfaceTint", Md::surface_tint),
+    ("primary", Md::primary),
+    ("onPrimary", Md::on_primary),
+    ("primaryContainer", Md::primary_container),
+    ("onPrimaryContainer", Md::on_primary_container),
+    ("inversePrimary", Md::inverse_primary),
+    ("secondary", Md::secondary),
+    ("onSecondary", Md::on_secondary),
+    ("secondaryContainer", Md::secondary_container),
+    ("onSecondaryContainer", Md::on_secondary_container),
+    ("tertiary", Md::tertiary),
+    ("onTertiary", Md::on_tertiary),
+    ("tertiaryContainer", Md::tertiary_container),
+    ("onTertiaryContainer", Md::on_tertiary_container),
+    ("error", Md::error),
+    ("onError", Md::on_error),
+    ("errorContainer", Md::error_container),
+    ("onErrorContainer", Md::on_error_container),
+    ("primaryFixed", Md::primary_fixed),
+    ("primaryFixedDim", Md::primary_fixed_dim),
+    ("onPrimaryFixed", Md::on_primary_fixed),
+    ("onPrimaryFixedVariant", Md::on_primary_fixed_variant),
+    ("secondaryFixed", Md::secondary_fixed),
+    ("secondaryFixedDim", Md::secondary_fixed_dim),
+    ("onSecondaryFixed", Md::on_secondary_fixed),
+    ("onSecondaryFixedVariant", Md::on_secondary_fixed_variant),
+    ("tertiaryFixed", Md::tertiary_fixed),
+    ("tertiaryFixedDim", Md::tertiary_fixed_dim),
+    ("onTertiaryFixed", Md::on_tertiary_fixed),
+    ("onTertiaryFixedVariant", Md::on_tertiary_fixed_variant),
+];
+
+/// A generated scheme: role name to RGB.
+pub(crate) struct Scheme {
+    pub variant: Variant,
+    pub dark: bool,
+    pub seed: [u8; 3],
+    pub colors: Vec<(&'static str, [u8; 3])>,
+}
+
+/// Pick the best source color of `img` with the Material quantizer and
+/// scorer, as Android does for wallpapers.
+pub(crate) fn seed_from_image(img: &DynamicImage) -> [u8; 3] {
+    let rgba = img
+        .resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle)
+        .to_rgba8();
+    let pixels: Vec<Argb> = rgba
+        .pixels()
+        .filter(|p| p[3] == 255)
+        .map(|p| Argb::new(p[3], p[0], p[1], p[2]))
+        .collect();
+    let quantized = QuantizerCelebi::quantize(&pixels, MAX_COLORS);
+    let seed = Score::score(&quantized.color_to_count, None, None, None)
+        .first()
+        .copied()
+        .unwrap_or_else(|| Argb::from_u32(DEFAULT_SEED));
+    [seed.red, seed.green, seed.blue]
+}
+
+/// Resolve every M3 role for `seed` in the given variant and mode.
+/// `contrast` ranges from -1 (reduced) through 0 (standard) to 1 (high).
+pub(crate) fn generate(seed: [u8; 3], variant: Variant, dark: bool, contrast: f64) -> Scheme {
+    let [r, g, b] = seed;
+    let scheme = variant.scheme(
+        Hct::new(Argb::new(255, r, g, b)),
+        dark,
+        contrast.clamp(-1.0, 1.0),
+    );
+    let colors = ROLES
+        .iter()
+        .map(|&(name, role)| {
+            let argb = role().get_argb(&scheme);
+            (name, [argb.red, argb.green, argb.blue])
+        })
+        .collect();
+    Scheme {
+        variant,
+        dark,
+        seed,
+        colors,
+    }
+}
+
+fn hex([r, g, b]: [u8; 3]) -> String {
+    format!("{r:02x}{g:02x}{b:02x}")
+}
+
+impl Scheme {
+    /// Same shape as the `vela scheme` JSON, so `Colors.load` can take it
+    /// after `JSON.stringify`.
+    fn to_map(&self) -> QVariantMap {
+        let mut colors = QVariantMap::default();
+        for (name, rgb) in &self.colors {
+            colors.insert(name, QVariant::from(&QString::from(hex(*rgb))));
+        }
+        let mut map = QVariantMap::default();
+        map.insert("name", QVariant::from(&QString::from("dynamic")));
+        map.insert("flavour", QVariant::from(&QString::from(self.variant.name())));
+        map.insert(
+            "mode",
+            QVariant::from(&QString::from(if self.dark { "dark" } else { "light" })),
+        );
+        map.insert("seed", QVariant::from(&QString::from(hex(self.seed))));
+        map.insert("colors", QVariant::from(&colors));
+        map
+    }
+}
+
+#[derive(QObject, Default)]
+pub struct SchemeGenerator;
+
+impl SchemeGenerator {
+    /// Build the full M3 role set from a seed color. Unknown variants fall
+    /// back to `tonalspot`.
+    #[qinvokable(cpp_name = "fromSeed")]
+    pub fn from_seed(&self, seed: &QColor, variant: &QString, dark: bool) -> QVariantMap {
+        let seed = [seed.red() as u8, seed.green() as u8, seed.blue() as u8];
+        generate(seed, parse_variant(variant), dark, 0.0).to_map()
+    }
+
+    /// Build the full M3 role set from the image at `path`. Decoding and
+    /// quantizing run on a worker thread; `callback` receives the scheme, or
+    /// an empty map if the image could not be read.
+    #[qinvokable(cpp_name = "fromImage")]
+    pub fn from_image(
+        &self,
+        path: &QString,
+        variant: &QString,
+        dark: bool,
+        callback: QJSValue,
+    ) {
+        let path = path.to_string();
+        let variant = parse_variant(variant);
+        let qt_thread = self.qt_thread();
+        thread::spawn(move || {
+            let scheme = match image_loader::load_frames(Path::new(&path), 1) {
+                Ok(frames) => Some(generate(seed_from_image(&frames[0]), variant, dark, 0.0)),
+                Err(e) => {
+                    eprintln!("SchemeGenerator: unable to open {path}: {e}");
+                    None
+                }
+            };
+            let _ = qt_thread.queue(move |_: &mut SchemeGenerator| {
+                if callback.is_callable() {
+                    let map = scheme.map(|s| s.to_map()).unwrap_or_default();
+                    callback.call(&[QJSValue::from(&QVariant::from(&map))]);
+                }
+            });
+        });
+    }
+}
+
+fn parse_variant(variant: &QString) -> Variant {
+    Variant::parse(&variant.to_string()).unwrap_or(Variant::TonalSpot)
+}
+
+pub fn register() {
+    cxx_qt::qml_register_type::<SchemeGenerator>("Vela", 1, 0, "SchemeGenerator");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLUE: [u8; 3] = [0x00, 0x00, 0xff];
+
+    const VARIANTS: [Variant; 9] = [
+        Variant::TonalSpot,
+        Variant::Vibrant,
+        Variant::Expressive,
+        Variant::Fidelity,
+        Variant::Content,
+        Variant::FruitSalad,
+        Variant::Rainbow,
+        Variant::Neutral,
+        Variant::Monochrome,
+    ];
+
+    fn role(scheme: &Scheme, name: &str) -> String {
+        let (_, rgb) = scheme.colors.iter().find(|(n, _)| *n == name).unwrap();
+        hex(*rgb)
+    }
+
+    fn assert_roles(scheme: &Scheme, expected: &[(&str, &str)]) {
+        for (name, value) in expected {
+            assert_eq!(
+                role(scheme, name),
+                *value,
+                "{name} in {} {}",
+                scheme.variant.name(),
+                if scheme.dark { "dark" } else { "light" }
+            );
+        }
+    }
+
+    // Expected values below are from material-color-utilities' scheme tests.
+
+    #[test]
+    fn tonal_spot_matches_reference() {
+        assert_roles(
+            &generate(BLUE, Variant::TonalSpot, false, 0.0),
+            &[
+                ("primary", "555992"),
+                ("primaryContainer", "e0e0ff"),
+                ("surface", "fbf8ff"),
+                ("onSurface", "1b1b21"),
+            ],
+        );
+        assert_roles(
+            &generate(BLUE, Variant::TonalSpot, true, 0.0),
+            &[
+                ("primary", "bec2ff"),
+                ("primaryContainer", "3e4278"),
+                ("onPrimaryContainer", "e0e0ff"),
+                ("surface", "131318"),
+                ("onSurface", "e4e1e9"),
+            ],
+        );
+    }
+
+    #[test]
+    fn vibrant_matches_reference() {
+        assert_roles(
+            &generate(BLUE, Variant::Vibrant, false, 0.0),
+            &[("primary", "343dff"), ("primaryContainer", "e0e0ff")],
+        );
+        assert_roles(
+            &generate(BLUE, Variant::Vibrant, true, 0.0),
+            &[
+                ("primary", "bec2ff"),
+                ("primaryContainer", "0000ef"),
+                ("onPrimaryContainer", "e0e0ff"),
+            ],
+        );
+    }
+
+    #[test]
+    fn monochrome_is_grey() {
+        assert_roles(
+            &generate(BLUE, Variant::Monochrome, false, 0.0),
+            &[
+                ("primary", "000000"),
+                ("surface", "f9f9f9"),
+                ("onSurface", "1b1b1b"),
+                ("surfaceContainer", "eeeeee"),
+                ("surfaceContainerHighest", "e2e2e2"),
+                ("outline", "777777"),
+            ],
+        );
+        assert_roles(
+            &generate(BLUE, Variant::Monochrome, true, 0.0),
+            &[
+                ("primary", "ffffff"),
+                ("surface", "131313"),
+                ("onSurface", "e2e2e2"),
+                ("surfaceContainer", "1f1f1f"),
+                ("surfaceContainerHighest", "353535"),
+                ("outline", "919191"),
+            ],
+        );
+    }
+
+    /// The error palette, shadow and scrim are fixed in every variant.
+    #[test]
+    fn every_variant_has_reference_fixed_roles() {
+        for seed in [BLUE, [0x67, 0x50, 0xa4], [0xb3, 0x26, 0x1e]] {
+            for variant in VARIANTS {
+                let light = generate(seed, variant, false, 0.0);
+                assert_eq!(light.colors.len(), ROLES.len());
+                assert_roles(
+                    &light,
+                    &[
+                        ("error", "ba1a1a"),
+                        ("onError", "ffffff"),
+                        ("errorContainer", "ffdad6"),
+                        ("shadow", "000000"),
+                        ("scrim", "000000"),
+                    ],
+                );
+                let dark = generate(seed, variant, true, 0.0);
+                assert_roles(
+                    &dark,
+                    &[
+                        ("error", "ffb4ab"),
+                        ("onError", "690005"),
+                        ("errorContainer", "93000a"),
+                        ("onErrorContainer", "ffdad6"),
+                        ("shadow", "000000"),
+                        ("scrim", "000000"),
+                    ],
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn generation_is_deterministic() {
+        for variant in VARIANTS {
+            for dark in [false, true] {
+                let a = generate(BLUE, variant, dark, 0.0);
+                let b = generate(BLUE, variant, dark, 0.0);
+                assert_eq!(a.colors, b.colors, "{}", variant.name());
+            }
+        }
+    }
+
+    #[test]
+    fn variant_names_round_trip() {
+        for variant in VARIANTS {
+            assert!(Variant::parse(variant.name()) == Some(variant));
+        }
+    }
+}
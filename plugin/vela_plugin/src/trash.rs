@@ -0,0 +1,462 @@
+use chrono::{Local, NaiveDateTime};
+
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::file_ops::{self, FileError, FileErrorKind, FileResult};
+use crate::url;
+
+const DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+/// A file sitting in one of the trash directories.
+pub(crate) struct TrashEntry {
+    /// Path of the trashed file under `<trash>/files`; used as its id.
+    pub id: PathBuf,
+    pub original_path: PathBuf,
+    pub deletion_date: Option<NaiveDateTime>,
+    pub is_dir: bool,
+}
+
+/// Move `path` to the trash per the freedesktop Trash spec: the home trash
+/// when `path` is on the same filesystem, otherwise `$topdir/.Trash/$uid` or
+/// `$topdir/.Trash-$uid` of its mount. Returns the id for `restore`.
+pub(crate) fn trash(path: &Path) -> FileResult<PathBuf> {
+    let path = std::path::absolute(path).map_err(|e| FileError::io(e, "resolve", path))?;
+    let meta = fs::symlink_metadata(&path).map_err(|e| FileError::io(e, "stat", &path))?;
+
+    let home_trash = home_trash();
+    let home_dev = fs::create_dir_all(&home_trash)
+        .and_then(|_| fs::metadata(&home_trash))
+        .map(|m| m.dev())
+        .ok();
+    let (trash_dir, recorded) = if home_dev == Some(meta.dev()) {
+        (home_trash, path.clone())
+    } else {
+        let top = mount_point(&path, meta.dev());
+        let dir = topdir_trash(&top).ok_or_else(|| {
+            FileError::new(
+                FileErrorKind::Permission,
+                format!("no usable trash directory on {}", top.display()),
+            )
+        })?;
+        let relative = path.strip_prefix(&top).unwrap_or(&path).to_path_buf();
+        (dir, relative)
+    };
+
+    let files = trash_dir.join("files");
+    let info = trash_dir.join("info");
+    for dir in [&files, &info] {
+        fs::create_dir_all(dir).map_err(|e| FileError::io(e, "create", dir))?;
+    }
+
+    let contents = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        url::percent_encode(recorded.as_os_str().as_bytes()),
+        Local::now().format(DATE_FORMAT),
+    );
+    let name = path
+        .file_name()
+        .map(|n| n.to_os_string())
+        .unwrap_or_else(|| OsString::from("file"));
+
+    // The .trashinfo is created exclusively first, which reserves the name
+    // against other processes trashing a file with the same name.
+    for n in 1u32.. {
+        let candidate = unique_name(&name, n);
+        let info_path = info.join(info_file_name(&candidate));
+        let opened = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&info_path);
+        let mut file = match opened {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(FileError::io(e, "create", &info_path)),
+        };
+        let target = files.join(&candidate);
+        let moved = file
+            .write_all(contents.as_bytes())
+            .map_err(|e| FileError::io(e, "write", &info_path))
+            .and_then(|_| {
+                fs::rename(&path, &target).map_err(|e| FileError::io(e, "trash", &path))
+            });
+        if let Err(e) = moved {
+            let _ = fs::remove_file(&info_path);
+            return Err(e);
+        }
+        return Ok(target);
+    }
+    unreachable!()
+}
+
+/// Move a trashed file back to where it came from. Fails with `Exists`
+/// rather than overwrite a file that has since taken its place.
+pub(crate) fn restore(id: &Path) -> FileResult<PathBuf> {
+    // Only ever move files out of a trash, never arbitrary paths handed in
+    // from QML.
+    let in_trash = id.file_name().is_some()
+        && id
+            .parent()
+            .is_some_and(|files| trash_dirs().iter().any(|dir| dir.join("files") == files));
+    let entry = in_trash.then(|| read_entry(id)).flatten().ok_or_else(|| {
+        FileError::new(
+            FileErrorKind::NotFound,
+            format!("{} is not in the trash", id.display()),
+        )
+    })?;
+    let target = entry.original_path;
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).map_err(|e| FileError::io(e, "create", parent))?;
+    }
+    move_no_clobber(id, &target, entry.is_dir)?;
+    if let Some(info) = info_path(id) {
+        let _ = fs::remove_file(info);
+    }
+    Ok(target)
+}
+
+/// Move `id` to `target` unless something exists there, checked atomically
+/// with the move. Files go through `move_path`, which hard links first. A
+/// directory cannot be hard linked, and rename would replace an empty
+/// directory, so it is renamed over an empty placeholder created
+/// exclusively for it instead.
+fn move_no_clobber(id: &Path, target: &Path, is_dir: bool) -> FileResult {
+    if !is_dir {
+        return file_ops::move_path(id, target, false);
+    }
+    fs::create_dir(target).map_err(|e| FileError::io(e, "restore to", target))?;
+    match fs::rename(id, target) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_dir(target);
+            if e.kind() == ErrorKind::CrossesDevices {
+                return file_ops::move_path(id, target, false);
+            }
+            Err(FileError::io(e, "restore", target))
+        }
+    }
+}
+
+/// Everything in the home trash and the trash of each mounted filesystem,
+/// most recently deleted first.
+pub(crate) fn list() -> Vec<TrashEntry> {
+    let mut entries: Vec<TrashEntry> = trash_dirs()
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir.join("files")).ok())
+        .flatten()
+        .flatten()
+        .filter_map(|f| read_entry(&f.path()))
+        .collect();
+    entries.sort_by(|a, b| b.deletion_date.cmp(&a.deletion_date));
+    entries
+}
+
+/// The home trash and every possible trash directory on mounted
+/// filesystems, each once even if mounted several times.
+fn trash_dirs() -> Vec<PathBuf> {
+    let uid = uid();
+    let mut dirs = vec![home_trash()];
+    for top in mount_points() {
+        dirs.push(top.join(".Trash").join(uid.to_string()));
+        dirs.push(top.join(format!(".Trash-{uid}")));
+    }
+    dirs.sort();
+    dirs.dedup();
+    dirs
+}
+
+/// Parse the `.trashinfo` belonging to the trashed file `id`.
+fn read_entry(id: &Path) -> Option<TrashEntry> {
+    let trash_dir = id.parent()?.parent()?;
+    let contents = fs::read_to_string(info_path(id)?).ok()?;
+    let mut lines = contents.lines().map(str::trim);
+    if lines.next()? != "[Trash Info]" {
+        return None;
+    }
+    let mut path = None;
+    let mut date = None;
+    for line in lines {
+        if let Some(p) = line.strip_prefix("Path=") {
+            path = url::percent_decode(p).map(OsString::from_vec).map(PathBuf::from);
+        } else if let Some(d) = line.strip_prefix("DeletionDate=") {
+            date = NaiveDateTime::parse_from_str(d, DATE_FORMAT).ok();
+        }
+    }
+    let path = path?;
+    // Relative paths are relative to the mount the trash lives on.
+    let original_path = if path.is_absolute() || trash_dir == home_trash() {
+        path
+    } else {
+        let top = trash_dir.parent()?;
+        let top = if top.ends_with(".Trash") { top.parent()? } else { top };
+        top.join(path)
+    };
+    Some(TrashEntry {
+        id: id.to_path_buf(),
+        original_path,
+        deletion_date: date,
+        is_dir: fs::symlink_metadata(id).is_ok_and(|m| m.is_dir()),
+    })
+}
+
+fn info_path(id: &Path) -> Option<PathBuf> {
+    let name = id.file_name()?;
+    Some(id.parent()?.parent()?.join("info").join(info_file_name(name)))
+}
+
+fn info_file_name(name: &std::ffi::OsStr) -> OsString {
+    let mut file = name.to_os_string();
+    file.push(".trashinfo");
+    file
+}
+
+/// `name` for the first attempt, then `name.2`, `name.3`, ... before any
+/// extension so the file keeps opening with the right application.
+fn unique_name(name: &std::ffi::OsStr, n: u32) -> OsString {
+    if n == 1 {
+        return name.to_os_string();
+    }
+    let path = Path::new(name);
+    match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(ext)) => {
+            let mut out = stem.to_os_string();
+            out.push(format!(".{n}."));
+            out.push(ext);
+            out
+        }
+        _ => {
+            let mut out = name.to_os_string();
+            out.push(format!(".{n}"));
+            out
+        }
+    }
+}
+
+fn home_trash() -> PathBuf {
+    let data = std::env::var_os("XDG_DATA_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            PathBuf::from(std::env::var_os("HOME").unwrap_or_default()).join(".local/share")
+        });
+    data.join("Trash")
+}
+
+/// Trash directory for the mount at `top`: the admin-provided
+/// `$top/.Trash/$uid` if `$top/.Trash` is a sticky, non-symlink directory,
+/// otherwise `$top/.Trash-$uid`, created with mode 0700.
+fn topdir_trash(top: &Path) -> Option<PathBuf> {
+    let uid = uid();
+    let shared = top.join(".Trash");
+    if let Ok(meta) = fs::symlink_metadata(&shared) {
+        if meta.is_dir() && meta.permissions().mode() & 0o1000 != 0 {
+            let dir = shared.join(uid.to_string());
+            if fs::create_dir_all(&dir).is_ok() {
+                return Some(dir);
+            }
+        }
+    }
+    let own = top.join(format!(".Trash-{uid}"));
+    if !own.is_dir() {
+        fs::create_dir(&own).ok()?;
+        fs::set_permissions(&own, fs::Permissions::from_mode(0o700)).ok()?;
+    }
+    let meta = fs::symlink_metadata(&own).ok()?;
+    (meta.is_dir() && meta.uid() == uid).then_some(own)
+}
+
+/// Topmost ancestor of `path` still on device `dev`.
+fn mount_point(path: &Path, dev: u64) -> PathBuf {
+    let mut top = path.to_path_buf();
+    for ancestor in path.ancestors().skip(1) {
+        match fs::metadata(ancestor) {
+            Ok(m) if m.dev() == dev => top = ancestor.to_path_buf(),
+            _ => break,
+        }
+    }
+    top
+}
+
+/// Mount points from `/proc/self/mounts`, with octal escapes decoded.
+fn mount_points() -> Vec<PathBuf> {
+    let Ok(mounts) = fs::read_to_string("/proc/self/mounts") else {
+        return Vec::new();
+    };
+    mounts
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(unescape_mount_field)
+        .collect()
+}
+
+/// Decode the `\040`-style octal escapes the kernel writes for spaces, tabs,
+/// newlines and backslashes in mount paths.
+fn unescape_mount_field(field: &str) -> PathBuf {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes
+            .get(i + 1..i + 4)
+            .filter(|o| o.iter().all(|d| (b'0'..=b'7').contains(d)))
+            .and_then(|o| u8::from_str_radix(std::str::from_utf8(o).ok()?, 8).ok());
+        match (bytes[i], octal) {
+            (b'\\', Some(b)) => {
+                out.push(b);
+                i += 4;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(OsString::from_vec(out))
+}
+
+/// Real uid of this process, without pulling in libc.
+fn uid() -> u32 {
+    fs::metadata("/proc/self").map_or(0, |m| m.uid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("vela-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Put `name` into the trash at `trash_dir` by hand, recording `path`.
+    fn trashed(trash_dir: &Path, name: &str, path: &str) -> PathBuf {
+        for dir in ["files", "info"] {
+            fs::create_dir_all(trash_dir.join(dir)).unwrap();
+        }
+        let id = trash_dir.join("files").join(name);
+        fs::write(&id, "contents").unwrap();
+        fs::write(
+            trash_dir.join("info").join(format!("{name}.trashinfo")),
+            format!("[Trash Info]\nPath={path}\nDeletionDate=2024-03-15T10:20:30\n"),
+        )
+        .unwrap();
+        id
+    }
+
+    #[test]
+    fn unique_name_numbers_before_the_extension() {
+        let name = |name: &str, n| unique_name(OsStr::new(name), n);
+        assert_eq!(name("photo.jpg", 1), "photo.jpg");
+        assert_eq!(name("photo.jpg", 2), "photo.2.jpg");
+        assert_eq!(name("archive.tar.gz", 3), "archive.tar.3.gz");
+        assert_eq!(name("README", 2), "README.2");
+        assert_eq!(name(".bashrc", 2), ".bashrc.2");
+    }
+
+    #[test]
+    fn read_entry_resolves_paths_against_the_mount() {
+        let tmp = TempDir::new("trash-entry");
+        let own = tmp.0.join(".Trash-1000");
+        let id = trashed(&own, "a b.txt", "docs/a%20b.txt");
+        let entry = read_entry(&id).unwrap();
+        assert_eq!(entry.id, id);
+        assert_eq!(entry.original_path, tmp.0.join("docs/a b.txt"));
+        assert_eq!(
+            entry.deletion_date,
+            NaiveDateTime::parse_from_str("2024-03-15T10:20:30", DATE_FORMAT).ok()
+        );
+        assert!(!entry.is_dir);
+
+        let shared = tmp.0.join(".Trash/1000");
+        let id = trashed(&shared, "c", "c");
+        assert_eq!(read_entry(&id).unwrap().original_path, tmp.0.join("c"));
+
+        let id = trashed(&own, "d", "/elsewhere/d");
+        assert_eq!(
+            read_entry(&id).unwrap().original_path,
+            PathBuf::from("/elsewhere/d")
+        );
+    }
+
+    #[test]
+    fn read_entry_rejects_bad_info() {
+        let tmp = TempDir::new("trash-bad-info");
+        let own = tmp.0.join(".Trash-1000");
+        let id = trashed(&own, "a", "a");
+        fs::write(own.join("info/a.trashinfo"), "Path=a\n").unwrap();
+        assert!(read_entry(&id).is_none());
+        fs::write(own.join("info/a.trashinfo"), "[Trash Info]\nPath=a%2\n").unwrap();
+        assert!(read_entry(&id).is_none());
+        fs::remove_file(own.join("info/a.trashinfo")).unwrap();
+        assert!(read_entry(&id).is_none());
+    }
+
+    #[test]
+    fn mount_fields_are_unescaped() {
+        assert_eq!(unescape_mount_field("/"), PathBuf::from("/"));
+        assert_eq!(
+            unescape_mount_field(r"/media/My\040Disk\011x\134y"),
+            PathBuf::from("/media/My Disk\tx\\y")
+        );
+        // Anything but three octal digits is kept as written.
+        assert_eq!(
+            unescape_mount_field(r"/a\08b\+12\1"),
+            PathBuf::from(r"/a\08b\+12\1")
+        );
+        assert_eq!(
+            unescape_mount_field(r"/\377").as_os_str().as_bytes(),
+            b"/\xff"
+        );
+    }
+
+    #[test]
+    fn move_no_clobber_keeps_existing_targets() {
+        let tmp = TempDir::new("trash-restore");
+        let file = tmp.0.join("file");
+        let dir = tmp.0.join("dir");
+        fs::write(&file, "trashed").unwrap();
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("inner"), "x").unwrap();
+
+        let taken = tmp.0.join("taken");
+        fs::write(&taken, "new").unwrap();
+        let err = move_no_clobber(&file, &taken, false).unwrap_err();
+        assert_eq!(err.kind, FileErrorKind::Exists);
+        assert_eq!(fs::read_to_string(&taken).unwrap(), "new");
+        assert!(file.exists());
+
+        // rename(2) would happily replace an empty directory.
+        let empty = tmp.0.join("empty");
+        fs::create_dir(&empty).unwrap();
+        let err = move_no_clobber(&dir, &empty, true).unwrap_err();
+        assert_eq!(err.kind, FileErrorKind::Exists);
+        assert!(dir.join("inner").exists());
+        assert!(empty.read_dir().unwrap().next().is_none());
+
+        move_no_clobber(&file, &tmp.0.join("restored"), false).unwrap();
+        move_no_clobber(&dir, &tmp.0.join("restored-dir"), true).unwrap();
+        assert!(!file.exists() && !dir.exists());
+        assert_eq!(
+            fs::read_to_string(tmp.0.join("restored")).unwrap(),
+            "trashed"
+        );
+        assert!(tmp.0.join("restored-dir/inner").exists());
+    }
+}
@@ -0,0 +1,211 @@
+use std::ffi::OsString;
+use std::fs;
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+
+/// Local filesystem path for `url`, per RFC 8089. Accepts `file:/path`,
+/// `file:///path`, and `file://host/path` when the host is empty,
+/// `localhost` or this machine's hostname. Percent-escapes are decoded to
+/// raw bytes, so non-UTF-8 names survive, and a leading `~` expands to
+/// `$HOME`. Strings without a scheme are taken as paths. Returns `None`
+/// for other schemes, remote hosts and malformed escapes.
+pub(crate) fn local_path(url: &str) -> Option<PathBuf> {
+    let Some((scheme, rest)) = split_scheme(url) else {
+        return Some(expand_home(url.as_bytes().to_vec()));
+    };
+    if !scheme.eq_ignore_ascii_case("file") {
+        return None;
+    }
+
+    // Query and fragment are not part of the path.
+    let rest = rest.split(['?', '#']).next().unwrap_or_default();
+    let path = match rest.strip_prefix("//") {
+        Some(authority_path) => {
+            let (host, path) = match authority_path.find('/') {
+                Some(i) => authority_path.split_at(i),
+                None => (authority_path, ""),
+            };
+            if !is_local_host(host) {
+                return None;
+            }
+            path
+        }
+        None => rest,
+    };
+    if path.is_empty() {
+        return None;
+    }
+    let bytes = percent_decode(path)?;
+    if bytes.contains(&0) {
+        return None;
+    }
+    Some(expand_home(bytes))
+}
+
+/// `(scheme, rest)` if `url` starts with an RFC 3986 scheme.
+fn split_scheme(url: &str) -> Option<(&str, &str)> {
+    let (scheme, rest) = url.split_once(':')?;
+    let mut chars = scheme.chars();
+    let valid = chars.next()?.is_ascii_alphabetic()
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    valid.then_some((scheme, rest))
+}
+
+fn is_local_host(host: &str) -> bool {
+    if host.is_empty() || host.eq_ignore_ascii_case("localhost") {
+        return true;
+    }
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| fs::read_to_string("/etc/hostname"))
+        .is_ok_and(|name| name.trim().eq_ignore_ascii_case(host))
+}
+
+pub(crate) fn percent_decode(s: &str) -> Option<Vec<u8>> {
+    let encoded = s.as_bytes();
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        if encoded[i] == b'%' {
+            let hex = encoded.get(i + 1..i + 3)?;
+            // from_str_radix would also take a sign, as in `%+F`.
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            i += 3;
+        } else {
+            bytes.push(encoded[i]);
+            i += 1;
+        }
+    }
+    Some(bytes)
+}
+
+/// Escape everything but unreserved characters and `/`, as `file:` URIs
+/// and `.trashinfo` paths expect.
+pub(crate) fn percent_encode(bytes: &[u8]) -> String {
+    const UNRESERVED: &[u8] = b"-_.~/";
+    let mut out = String::with_capacity(bytes.len());
+    for &b in bytes {
+        if b.is_ascii_alphanumeric() || UNRESERVED.contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+/// Replace a leading `~` or `~/` with `$HOME`.
+fn expand_home(path: Vec<u8>) -> PathBuf {
+    let home = std::env::var_os("HOME").filter(|h| !h.is_empty());
+    match (home, path.as_slice()) {
+        (Some(home), [b'~']) => PathBuf::from(home),
+        (Some(home), [b'~', b'/', rest @ ..]) => {
+            PathBuf::from(home).join(OsString::from_vec(rest.to_vec()))
+        }
+        _ => PathBuf::from(OsString::from_vec(path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::ffi::OsStrExt;
+
+    #[test]
+    fn local_path_accepts_local_file_urls() {
+        for url in [
+            "file:///tmp/a b",
+            "file://localhost/tmp/a b",
+            "file:/tmp/a b",
+        ] {
+            assert_eq!(
+                local_path(&url.replace(' ', "%20")),
+                Some(PathBuf::from("/tmp/a b"))
+            );
+        }
+        assert_eq!(
+            local_path("FILE://LOCALHOST/tmp"),
+            Some(PathBuf::from("/tmp"))
+        );
+        assert_eq!(
+            local_path("file:///tmp/x?query#frag"),
+            Some(PathBuf::from("/tmp/x"))
+        );
+    }
+
+    #[test]
+    fn local_path_rejects_remote_hosts_and_other_schemes() {
+        assert_eq!(local_path("file://example.invalid/tmp/a"), None);
+        assert_eq!(local_path("file://"), None);
+        assert_eq!(local_path("https://example.com/a"), None);
+        assert_eq!(local_path("trash:/a"), None);
+    }
+
+    #[test]
+    fn local_path_keeps_non_utf8_bytes() {
+        let path = local_path("file:///tmp/caf%E9").unwrap();
+        assert_eq!(path.as_os_str().as_bytes(), b"/tmp/caf\xE9");
+        assert_eq!(local_path("file:///tmp/a%00b"), None);
+    }
+
+    #[test]
+    fn local_path_rejects_malformed_escapes() {
+        assert_eq!(local_path("file:///tmp/a%2"), None);
+        assert_eq!(local_path("file:///tmp/a%"), None);
+        assert_eq!(local_path("file:///tmp/a%zz"), None);
+    }
+
+    #[test]
+    fn local_path_takes_plain_strings_as_paths() {
+        assert_eq!(local_path("/tmp/a%20b"), Some(PathBuf::from("/tmp/a%20b")));
+        assert_eq!(local_path("relative"), Some(PathBuf::from("relative")));
+        // Not a scheme, as it does not start with a letter.
+        assert_eq!(local_path("1:/x"), Some(PathBuf::from("1:/x")));
+    }
+
+    #[test]
+    fn split_scheme_follows_rfc_3986() {
+        assert_eq!(split_scheme("file:///a"), Some(("file", "///a")));
+        assert_eq!(split_scheme("svn+ssh://h/a"), Some(("svn+ssh", "//h/a")));
+        assert_eq!(split_scheme("/a:b"), None);
+        assert_eq!(split_scheme("a b:c"), None);
+        assert_eq!(split_scheme("no-colon"), None);
+    }
+
+    #[test]
+    fn percent_decode_handles_escapes() {
+        assert_eq!(percent_decode("a%20b%2Fc").as_deref(), Some(&b"a b/c"[..]));
+        assert_eq!(percent_decode("%ff%FE").as_deref(), Some(&[0xff, 0xfe][..]));
+        assert_eq!(percent_decode("%").as_deref(), None);
+        assert_eq!(percent_decode("%4").as_deref(), None);
+        assert_eq!(percent_decode("%+F").as_deref(), None);
+        assert_eq!(percent_decode("%g0").as_deref(), None);
+        assert_eq!(percent_decode("%%41").as_deref(), None);
+    }
+
+    #[test]
+    fn percent_encode_round_trips() {
+        let bytes = b"/tmp/a b%\xE9~";
+        assert_eq!(percent_encode(bytes), "/tmp/a%20b%25%E9~");
+        assert_eq!(
+            percent_decode(&percent_encode(bytes)).as_deref(),
+            Some(&bytes[..])
+        );
+    }
+
+    #[test]
+    fn expand_home_only_replaces_a_leading_tilde() {
+        let Some(home) = std::env::var_os("HOME").filter(|h| !h.is_empty()) else {
+            return;
+        };
+        let home = PathBuf::from(home);
+        assert_eq!(expand_home(b"~".to_vec()), home);
+        assert_eq!(expand_home(b"~/a/b".to_vec()), home.join("a/b"));
+        assert_eq!(local_path("file:///~/a"), Some(PathBuf::from("/~/a")));
+        assert_eq!(local_path("~/a"), Some(home.join("a")));
+        assert_eq!(expand_home(b"~user/a".to_vec()), PathBuf::from("~user/a"));
+        assert_eq!(expand_home(b"/a/~".to_vec()), PathBuf::from("/a/~"));
+    }
+}
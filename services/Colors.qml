@@ -124,8 +124,10 @@ Singleton {
 
         function onCurrentChanged(): void {
             const current = Wallpapers.current;
-            CUtils.getAverageLuminance(current, l => {
-                                           if (Wallpapers.current == current)
+            CUtils.getAverageLuminance(current, (l, error) => {
+                                           if (error)
+                                           console.warn("Colors: unable to analyse wallpaper:", error);
+                                           else if (Wallpapers.current == current)
                                            root.wallLuminance = l;
                                        });
         }
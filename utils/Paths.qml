@@ -25,7 +25,7 @@ Singleton {
     }
 
     function absolutePath(path: string): string {
-        return toLocalFile(path.replace("~", home));
+        return toLocalFile(path.replace(/^~(?=\/|$)/, home));
     }
 
     function shortenHome(path: string): string {